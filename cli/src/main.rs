@@ -2,24 +2,238 @@
 #![warn(missing_debug_implementations)]
 
 use anyhow::anyhow;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[tokio::main]
 pub async fn main() -> Result<(), anyhow::Error> {
     let args = ChimneyArgs::parse();
-    let config = chimney_server::ServerConfig::from_file(args.config)?;
+    match args.command {
+        None => serve(args.config).await,
+        Some(Command::Check) => check(args.config),
+        Some(Command::Effective {
+            machine_id,
+            preflight,
+        }) => effective(args.config, &machine_id, preflight),
+        Some(Command::Import { export, into }) => import(&export, &into),
+        Some(Command::MigrateMoroz { dir, out }) => migrate_moroz(&dir, &out),
+        Some(Command::Openapi { api }) => openapi(api),
+        Some(Command::Simulate(args)) => simulate(args).await,
+    }
+}
+
+async fn serve(config: Option<String>) -> Result<(), anyhow::Error> {
+    let config = chimney_server::ServerConfig::from_file(config)?;
     let server = chimney_server::start_server(config).await?;
 
-    println!("Starting chimney sync server on {}", server.local_addr());
+    println!("Starting chimney sync server on {}", server.local_addr);
     server
+        .server
         .await
         .map_err(|error| anyhow!("Terminating chimney sync server: {}", error))
 }
 
+fn check(config: Option<String>) -> Result<(), anyhow::Error> {
+    let problems = chimney_server::check(config)?;
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+    // Warnings are printed, but the server would still load the configuration.
+    match problems.iter().filter(|problem| problem.is_error()).count() {
+        0 => {
+            println!("Configuration is valid");
+            Ok(())
+        }
+        1 => Err(anyhow!("Found 1 problem")),
+        count => Err(anyhow!("Found {} problems", count)),
+    }
+}
+
+fn effective(
+    config: Option<String>,
+    machine_id: &str,
+    preflight: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let config = chimney_server::ServerConfig::from_file(config)?;
+    let options = chimney_server::ConfigStoreOptions {
+        mode: config.client_config_mode,
+        identifier_validation: config.rule_identifier_validation,
+    };
+    let store =
+        chimney_server::ConfigStore::from_path_with_options(&config.client_config_path, options)?;
+    let preflight = preflight.map(read_preflight).transpose()?;
+    let effective = store.effective(machine_id, preflight.as_ref())?;
+    println!("{}", serde_json::to_string_pretty(&effective)?);
+    Ok(())
+}
+
+fn read_preflight(path: String) -> Result<santa_types::PreflightOptions, anyhow::Error> {
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow!("could not read \"{}\", {}", path, e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| anyhow!("could not parse preflight request \"{}\", {}", path, e))
+}
+
+fn import(export: &str, into: &str) -> Result<(), anyhow::Error> {
+    let rules = chimney_server::read_rule_export(export)?;
+    let mut file = match std::path::Path::new(into).exists() {
+        true => chimney_server::RuleFile::open(into)?,
+        false => chimney_server::RuleFile::create(into, "")?,
+    };
+    let summary = chimney_server::import_rules(&mut file, &rules)?;
+    file.save()
+        .map_err(|e| anyhow!("could not write \"{}\", {}", into, e))?;
+    println!(
+        "Imported {} rules into {}: {} added, {} updated, {} unchanged",
+        rules.len(),
+        into,
+        summary.added,
+        summary.updated,
+        summary.unchanged
+    );
+    Ok(())
+}
+
+fn migrate_moroz(dir: &str, out: &str) -> Result<(), anyhow::Error> {
+    let migration = chimney_server::migrate_moroz(dir.into(), out.into())?;
+    for unmapped in &migration.unmapped {
+        eprintln!("not migrated: {unmapped}");
+    }
+    for problem in &migration.problems {
+        eprintln!("{problem}");
+    }
+    println!("Wrote {} client configs to {}", migration.files.len(), out);
+    match migration.problems.len() {
+        0 => Ok(()),
+        1 => Err(anyhow!("Found 1 problem in the migrated configs")),
+        count => Err(anyhow!("Found {} problems in the migrated configs", count)),
+    }
+}
+
+fn openapi(api: Api) -> Result<(), anyhow::Error> {
+    let api = match api {
+        Api::Sync => chimney_server::Api::Sync,
+        Api::Admin => chimney_server::Api::Admin,
+    };
+    chimney_server::write_openapi(api, &mut std::io::stdout().lock())
+}
+
+async fn simulate(args: SimulateArgs) -> Result<(), anyhow::Error> {
+    // Without --ca-cert, https servers are checked against the bundled Mozilla roots.
+    let tls = Some(chimney_server::SimulateTls {
+        ca_file: args.ca_cert.map(Into::into),
+        client_cert_file: args.client_cert.map(Into::into),
+        client_key_file: args.client_key.map(Into::into),
+    });
+    let options = chimney_server::SimulateOptions {
+        url: args.url.parse()?,
+        machine_id: args.machine_id,
+        preflight: match args.preflight {
+            Some(path) => read_preflight(path)?,
+            None => chimney_server::simulated_preflight(),
+        },
+        events: match args.events {
+            Some(path) => chimney_server::read_events(path)?,
+            None => vec![],
+        },
+        headers: args
+            .headers
+            .iter()
+            .map(|header| chimney_server::parse_header(header))
+            .collect::<Result<_, _>>()?,
+        tls,
+    };
+    let report = chimney_server::simulate(&options).await?;
+    println!("preflight: {}", serde_json::to_string(&report.preflight)?);
+    println!(
+        "eventupload: {} events in {} requests",
+        report.events_uploaded, report.eventupload_requests
+    );
+    println!(
+        "ruledownload: {} rules in {} requests",
+        report.rules.len(),
+        report.ruledownload_requests
+    );
+    println!("postflight: ok");
+    Ok(())
+}
+
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
 struct ChimneyArgs {
     /// Path to the server configuration file
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     config: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// chimney runs the sync server when no command is given.
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check the server config and the client configs it points to without starting the server
+    Check,
+    /// Print the preflight response and rules the server sends a machine, and the files they came from
+    Effective {
+        #[arg(long)]
+        machine_id: String,
+        /// Path to a preflight request body, to also consider configs with a selector
+        #[arg(long)]
+        preflight: Option<String>,
+    },
+    /// Import the rules in a `santactl rule --export` file into a client config file
+    Import {
+        /// Path to the rule export
+        export: String,
+        /// Path to the client config file, which is created if it does not exist
+        #[arg(long)]
+        into: String,
+    },
+    /// Convert a moroz config directory into chimney client configs
+    MigrateMoroz {
+        /// Path to the moroz config directory
+        dir: String,
+        /// Directory to write the client configs to, which must be empty or not exist
+        #[arg(long)]
+        out: String,
+    },
+    /// Print the OpenAPI document for one of chimney's APIs
+    Openapi {
+        #[arg(value_enum, default_value_t = Api::Sync)]
+        api: Api,
+    },
+    /// Run a sync against a chimney server the way a Santa client would
+    Simulate(SimulateArgs),
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Api {
+    Sync,
+    Admin,
+}
+
+#[derive(Debug, Args)]
+struct SimulateArgs {
+    /// URL of the chimney server, e.g. https://chimney.example.com:8443
+    url: String,
+    #[arg(long)]
+    machine_id: String,
+    /// Path to a preflight request body to send instead of a simulated one
+    #[arg(long)]
+    preflight: Option<String>,
+    /// Path to an eventupload request body whose events are uploaded after preflight
+    #[arg(long)]
+    events: Option<String>,
+    /// Header to send with every request, as "Name: value"
+    #[arg(short = 'H', long = "header")]
+    headers: Vec<String>,
+    /// CA certificate to trust for https URLs, instead of the bundled Mozilla roots
+    #[arg(long)]
+    ca_cert: Option<String>,
+    /// Client certificate to present when the server asks for one
+    #[arg(long, requires = "client_key")]
+    client_cert: Option<String>,
+    /// Private key of the client certificate
+    #[arg(long, requires = "client_cert")]
+    client_key: Option<String>,
 }
@@ -0,0 +1,93 @@
+use dropshot::{
+    HttpError, HttpResponseCreated, HttpResponseDeleted, HttpResponseOk, Path, Query,
+    RequestContext, TypedBody,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::api::MachineId;
+use crate::inventory::{Machine, Machines};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScopeKind {
+    Global,
+    Group,
+    Machine,
+}
+
+/// The client config whose rules are managed. `name` is the group name or machine id, and is not
+/// used for the global scope.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct RuleScope {
+    pub scope: ScopeKind,
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct RuleKey {
+    pub scope: ScopeKind,
+    pub name: Option<String>,
+    pub rule_type: santa_types::RuleType,
+    pub identifier: String,
+}
+
+#[dropshot::api_description]
+pub(crate) trait ChimneyAdminApi {
+    type Context;
+
+    #[endpoint(
+        method = GET,
+        path = "/admin/rules",
+    )]
+    async fn rules_get(
+        rqctx: RequestContext<Self::Context>,
+        query_params: Query<RuleScope>,
+    ) -> Result<HttpResponseOk<santa_types::Rules>, HttpError>;
+
+    #[endpoint(
+        method = POST,
+        path = "/admin/rules",
+    )]
+    async fn rules_post(
+        rqctx: RequestContext<Self::Context>,
+        query_params: Query<RuleScope>,
+        body_params: TypedBody<santa_types::Rule>,
+    ) -> Result<HttpResponseCreated<santa_types::Rule>, HttpError>;
+
+    #[endpoint(
+        method = PUT,
+        path = "/admin/rules",
+    )]
+    async fn rules_put(
+        rqctx: RequestContext<Self::Context>,
+        query_params: Query<RuleScope>,
+        body_params: TypedBody<santa_types::Rule>,
+    ) -> Result<HttpResponseOk<santa_types::Rule>, HttpError>;
+
+    #[endpoint(
+        method = DELETE,
+        path = "/admin/rules",
+    )]
+    async fn rules_delete(
+        rqctx: RequestContext<Self::Context>,
+        query_params: Query<RuleKey>,
+    ) -> Result<HttpResponseDeleted, HttpError>;
+
+    #[endpoint(
+        method = GET,
+        path = "/admin/machines",
+    )]
+    async fn machines_get(
+        rqctx: RequestContext<Self::Context>,
+    ) -> Result<HttpResponseOk<Machines>, HttpError>;
+
+    #[endpoint(
+        method = GET,
+        path = "/admin/machines/{machine_id}",
+    )]
+    async fn machine_get(
+        rqctx: RequestContext<Self::Context>,
+        path_params: Path<MachineId>,
+    ) -> Result<HttpResponseOk<Machine>, HttpError>;
+}
@@ -0,0 +1,315 @@
+use std::fs;
+
+use camino::Utf8Path;
+use dropshot::{
+    ClientErrorStatusCode, HttpError, HttpResponseCreated, HttpResponseDeleted, HttpResponseOk,
+    Path, Query, RequestContext, TypedBody,
+};
+use slog::warn;
+
+use crate::admin_api::{ChimneyAdminApi, RuleKey, RuleScope, ScopeKind};
+use crate::api::MachineId;
+use crate::auth::{bearer_token, is_accepted};
+
+use crate::config_store::{
+    ClientConfigMode, GLOBAL, GROUP, RuleFile, SharedConfigStore, is_safe_file_stem, same_rule,
+};
+use crate::inventory::{Machine, MachineInventory, Machines};
+use crate::{AdminContext, ChimneyAdminApiImpl};
+
+impl ChimneyAdminApi for ChimneyAdminApiImpl {
+    type Context = AdminContext;
+
+    async fn rules_get(
+        rqctx: RequestContext<Self::Context>,
+        query_params: Query<RuleScope>,
+    ) -> Result<HttpResponseOk<santa_types::Rules>, HttpError> {
+        authorize(&rqctx)?;
+        let scope = query_params.into_inner();
+        let rules = with_rule_files(rqctx.context(), move |store| {
+            let file = open_rule_file(store, &scope, false)?;
+            file.rules().map_err(internal_error)
+        })
+        .await?;
+        Ok(HttpResponseOk(santa_types::Rules {
+            rules,
+            cursor: None,
+        }))
+    }
+
+    async fn rules_post(
+        rqctx: RequestContext<Self::Context>,
+        query_params: Query<RuleScope>,
+        body_params: TypedBody<santa_types::Rule>,
+    ) -> Result<HttpResponseCreated<santa_types::Rule>, HttpError> {
+        authorize(&rqctx)?;
+        let scope = query_params.into_inner();
+        let rule = body_params.into_inner();
+        let created = rule.clone();
+        with_rule_files(rqctx.context(), move |store| {
+            let mut file = open_rule_file(store, &scope, true)?;
+            let mut rules = file.rules().map_err(internal_error)?;
+            if rules.iter().any(|existing| same_rule(existing, &rule)) {
+                return Err(HttpError::for_client_error(
+                    None,
+                    ClientErrorStatusCode::CONFLICT,
+                    format!("rule \"{}\" already exists", rule.identifier),
+                ));
+            }
+            rules.push(rule);
+            save_and_reload(store, &mut file, &rules)
+        })
+        .await?;
+        Ok(HttpResponseCreated(created))
+    }
+
+    async fn rules_put(
+        rqctx: RequestContext<Self::Context>,
+        query_params: Query<RuleScope>,
+        body_params: TypedBody<santa_types::Rule>,
+    ) -> Result<HttpResponseOk<santa_types::Rule>, HttpError> {
+        authorize(&rqctx)?;
+        let scope = query_params.into_inner();
+        let rule = body_params.into_inner();
+        let updated = rule.clone();
+        with_rule_files(rqctx.context(), move |store| {
+            let mut file = open_rule_file(store, &scope, false)?;
+            let mut rules = file.rules().map_err(internal_error)?;
+            let existing = rules
+                .iter_mut()
+                .find(|existing| same_rule(existing, &rule))
+                .ok_or_else(|| rule_not_found(&rule.identifier))?;
+            *existing = rule;
+            save_and_reload(store, &mut file, &rules)
+        })
+        .await?;
+        Ok(HttpResponseOk(updated))
+    }
+
+    async fn rules_delete(
+        rqctx: RequestContext<Self::Context>,
+        query_params: Query<RuleKey>,
+    ) -> Result<HttpResponseDeleted, HttpError> {
+        authorize(&rqctx)?;
+        let key = query_params.into_inner();
+        let scope = RuleScope {
+            scope: key.scope,
+            name: key.name,
+        };
+        with_rule_files(rqctx.context(), move |store| {
+            let mut file = open_rule_file(store, &scope, false)?;
+            let mut rules = file.rules().map_err(internal_error)?;
+            let count = rules.len();
+            rules.retain(|rule| {
+                !(rule.rule_type == key.rule_type && rule.identifier == key.identifier)
+            });
+            if rules.len() == count {
+                return Err(rule_not_found(&key.identifier));
+            }
+            save_and_reload(store, &mut file, &rules)
+        })
+        .await?;
+        Ok(HttpResponseDeleted())
+    }
+
+    async fn machines_get(
+        rqctx: RequestContext<Self::Context>,
+    ) -> Result<HttpResponseOk<Machines>, HttpError> {
+        authorize(&rqctx)?;
+        let inventory = inventory(rqctx.context())?;
+        Ok(HttpResponseOk(Machines {
+            machines: inventory.list(),
+        }))
+    }
+
+    async fn machine_get(
+        rqctx: RequestContext<Self::Context>,
+        path_params: Path<MachineId>,
+    ) -> Result<HttpResponseOk<Machine>, HttpError> {
+        authorize(&rqctx)?;
+        let machine_id = path_params.into_inner().machine_id;
+        let machine = inventory(rqctx.context())?
+            .get(&machine_id)
+            .ok_or_else(|| {
+                HttpError::for_client_error(
+                    None,
+                    ClientErrorStatusCode::NOT_FOUND,
+                    format!("machine \"{machine_id}\" not found"),
+                )
+            })?;
+        Ok(HttpResponseOk(machine))
+    }
+}
+
+fn authorize(rqctx: &RequestContext<AdminContext>) -> Result<(), HttpError> {
+    let headers = rqctx.request.headers();
+    match bearer_token(headers) {
+        Some(token) if is_accepted(token, &rqctx.context().tokens) => Ok(()),
+        _ => {
+            warn!(
+                rqctx.log,
+                "rejected admin request";
+                "remote_addr" => rqctx.request.remote_addr().to_string(),
+            );
+            Err(HttpError::for_client_error(
+                None,
+                ClientErrorStatusCode::UNAUTHORIZED,
+                "invalid or missing bearer token".to_string(),
+            ))
+        }
+    }
+}
+
+/// Runs `f` on the blocking pool while holding the edit lock, since rule changes read and write
+/// client configs and reload the store.
+async fn with_rule_files<T: Send + 'static>(
+    context: &AdminContext,
+    f: impl FnOnce(&SharedConfigStore) -> Result<T, HttpError> + Send + 'static,
+) -> Result<T, HttpError> {
+    let store = context.store.clone();
+    let edit_lock = context.edit_lock.clone();
+    tokio::task::spawn_blocking(move || {
+        let _guard = edit_lock.lock().unwrap();
+        f(&store)
+    })
+    .await
+    .map_err(|e| internal_error(e.into()))?
+}
+
+/// Opens the file that holds the rules for `scope`. If the file does not exist and `create` is
+/// set, a new file is started: in replace mode a new machine specific file starts as a copy of
+/// the global config, in the same format, because it has to be a complete client config.
+fn open_rule_file(
+    store: &SharedConfigStore,
+    scope: &RuleScope,
+    create: bool,
+) -> Result<RuleFile, HttpError> {
+    let store = store.load();
+    match scope.scope {
+        ScopeKind::Global => {
+            if scope.name.as_deref().is_some_and(|name| name != GLOBAL) {
+                return Err(HttpError::for_bad_request(
+                    None,
+                    "the global scope does not take a name".to_string(),
+                ));
+            }
+            open_config(&store.sources.configs[GLOBAL])
+        }
+        ScopeKind::Machine => {
+            let name = scope_name(scope)?;
+            if name == GLOBAL || !is_safe_file_stem(name) {
+                return Err(invalid_name(name));
+            }
+            match store.sources.configs.get(name) {
+                Some(path) => open_config(path),
+                None if create => match store.options.mode {
+                    ClientConfigMode::Replace => {
+                        let global = &store.sources.configs[GLOBAL];
+                        let contents =
+                            fs::read_to_string(global).map_err(|e| internal_error(e.into()))?;
+                        let file_name = format!("{name}.{}", global.extension().unwrap_or("toml"));
+                        RuleFile::create(store.path.join(file_name), &contents)
+                            .map_err(internal_error)
+                    }
+                    ClientConfigMode::Merge => {
+                        RuleFile::create(store.path.join(format!("{name}.toml")), "")
+                            .map_err(internal_error)
+                    }
+                },
+                None => Err(scope_not_found(scope)),
+            }
+        }
+        ScopeKind::Group => {
+            if store.options.mode != ClientConfigMode::Merge {
+                return Err(HttpError::for_bad_request(
+                    None,
+                    "groups require client_config_mode = \"merge\"".to_string(),
+                ));
+            }
+            let name = scope_name(scope)?;
+            if !name.split('/').all(is_safe_file_stem) {
+                return Err(invalid_name(name));
+            }
+            match store.sources.groups.get(name) {
+                Some(path) => open_config(path),
+                None if create => {
+                    RuleFile::create(store.path.join(name).join(format!("{GROUP}.toml")), "")
+                        .map_err(internal_error)
+                }
+                None => Err(scope_not_found(scope)),
+            }
+        }
+    }
+}
+
+/// Opens an existing client config in any of the formats the store reads.
+fn open_config(path: &Utf8Path) -> Result<RuleFile, HttpError> {
+    RuleFile::open(path).map_err(internal_error)
+}
+
+/// Saves the rules and reloads the store. The file is restored if the store rejects the change,
+/// so the files on disk always match the configs being served.
+fn save_and_reload(
+    store: &SharedConfigStore,
+    file: &mut RuleFile,
+    rules: &[santa_types::Rule],
+) -> Result<(), HttpError> {
+    let previous = fs::read_to_string(file.path()).ok();
+    file.set_rules(rules).map_err(internal_error)?;
+    file.save().map_err(|e| internal_error(e.into()))?;
+    if let Err(e) = store.reload() {
+        let restored = match previous {
+            Some(contents) => fs::write(file.path(), contents),
+            None => fs::remove_file(file.path()),
+        };
+        restored.map_err(|e| internal_error(e.into()))?;
+        return Err(HttpError::for_bad_request(
+            None,
+            format!("rule change rejected: {e:#}"),
+        ));
+    }
+    Ok(())
+}
+
+fn inventory(context: &AdminContext) -> Result<&MachineInventory, HttpError> {
+    context.inventory.as_deref().ok_or_else(|| {
+        HttpError::for_client_error(
+            None,
+            ClientErrorStatusCode::NOT_FOUND,
+            "machine inventory is not enabled".to_string(),
+        )
+    })
+}
+
+fn scope_name(scope: &RuleScope) -> Result<&str, HttpError> {
+    scope.name.as_deref().ok_or_else(|| {
+        HttpError::for_bad_request(None, "a name is required for this scope".to_string())
+    })
+}
+
+fn invalid_name(name: &str) -> HttpError {
+    HttpError::for_bad_request(None, format!("invalid name \"{name}\""))
+}
+
+fn scope_not_found(scope: &RuleScope) -> HttpError {
+    HttpError::for_client_error(
+        None,
+        ClientErrorStatusCode::NOT_FOUND,
+        format!(
+            "no config for \"{}\"",
+            scope.name.as_deref().unwrap_or_default()
+        ),
+    )
+}
+
+fn rule_not_found(identifier: &str) -> HttpError {
+    HttpError::for_client_error(
+        None,
+        ClientErrorStatusCode::NOT_FOUND,
+        format!("rule \"{identifier}\" not found"),
+    )
+}
+
+fn internal_error(error: anyhow::Error) -> HttpError {
+    HttpError::for_internal_error(format!("{error:#}"))
+}
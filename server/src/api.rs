@@ -1,8 +1,10 @@
-use dropshot::{HttpError, HttpResponseOk, Path, RequestContext};
+use dropshot::{Body, HttpError, HttpResponseOk, Path, RequestContext};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::body::CompressedTypedBody;
+use crate::body::{CompressedTypedBody, EventUploadBody};
+use crate::health::Health;
+use crate::response::CompressedResponse;
 
 #[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
 pub(crate) struct MachineId {
@@ -22,7 +24,7 @@ pub(crate) trait SantaSyncServerApi {
         rqctx: RequestContext<Self::Context>,
         path_params: Path<MachineId>,
         body_params: CompressedTypedBody<santa_types::PreflightOptions>,
-    ) -> Result<HttpResponseOk<santa_types::Preflight>, HttpError>;
+    ) -> Result<CompressedResponse<santa_types::Preflight>, HttpError>;
 
     #[endpoint(
         method = POST,
@@ -32,7 +34,7 @@ pub(crate) trait SantaSyncServerApi {
     async fn eventupload_post(
         rqctx: RequestContext<Self::Context>,
         path_params: Path<MachineId>,
-        body_params: CompressedTypedBody<santa_types::EventUploadOptions>,
+        body_params: EventUploadBody,
     ) -> Result<HttpResponseOk<santa_types::Empty>, HttpError>;
 
     #[endpoint(
@@ -43,7 +45,8 @@ pub(crate) trait SantaSyncServerApi {
     async fn ruledownload_post(
         rqctx: RequestContext<Self::Context>,
         path_params: Path<MachineId>,
-    ) -> Result<HttpResponseOk<santa_types::Rules>, HttpError>;
+        body_params: CompressedTypedBody<santa_types::RuleDownloadOptions>,
+    ) -> Result<CompressedResponse<santa_types::Rules>, HttpError>;
 
     #[endpoint(
         method = POST,
@@ -51,8 +54,33 @@ pub(crate) trait SantaSyncServerApi {
         content_type = "application/json",
     )]
     async fn postflight_post(
-        _rqctx: RequestContext<Self::Context>,
-        _path_params: Path<MachineId>,
+        rqctx: RequestContext<Self::Context>,
+        path_params: Path<MachineId>,
         _body_params: CompressedTypedBody<santa_types::PostflightOptions>,
     ) -> Result<HttpResponseOk<santa_types::Empty>, HttpError>;
+
+    #[endpoint(
+        method = GET,
+        path = "/metrics",
+    )]
+    async fn metrics_get(
+        rqctx: RequestContext<Self::Context>,
+    ) -> Result<http::Response<Body>, HttpError>;
+
+    #[endpoint(
+        method = GET,
+        path = "/healthz",
+    )]
+    async fn healthz_get(
+        rqctx: RequestContext<Self::Context>,
+    ) -> Result<HttpResponseOk<Health>, HttpError>;
+
+    /// Responds with 503 Service Unavailable when any check fails.
+    #[endpoint(
+        method = GET,
+        path = "/readyz",
+    )]
+    async fn readyz_get(
+        rqctx: RequestContext<Self::Context>,
+    ) -> Result<http::Response<Body>, HttpError>;
 }
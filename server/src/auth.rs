@@ -0,0 +1,206 @@
+use std::fs;
+use std::sync::{Arc, RwLock};
+
+use anyhow::anyhow;
+use camino::{Utf8Path, Utf8PathBuf};
+use http::{HeaderMap, HeaderName};
+use slog::{Logger, error, info};
+
+use crate::server_config::SyncAuthConfig;
+
+/// Checks the token Santa sends with every sync request, e.g. through `SyncExtraHeaders`.
+///
+/// Tokens come from the server config and an optional token file. Several tokens can be valid at
+/// once, so a new token can be rolled out before the old one is removed.
+#[derive(Debug)]
+pub struct SyncAuth {
+    accepted: RwLock<Vec<String>>,
+    header: HeaderName,
+    token_file: Option<Utf8PathBuf>,
+    tokens: Vec<String>,
+}
+
+impl SyncAuth {
+    pub fn from_config(config: &SyncAuthConfig) -> Result<Self, anyhow::Error> {
+        let header = HeaderName::try_from(config.header.as_str())
+            .map_err(|e| anyhow!("invalid sync auth header \"{}\", {}", config.header, e))?;
+        let auth = SyncAuth {
+            accepted: RwLock::new(Vec::new()),
+            header,
+            token_file: config.token_file.clone(),
+            tokens: config.tokens.clone(),
+        };
+        auth.reload()?;
+        Ok(auth)
+    }
+
+    /// Reads the token file again. The current tokens stay in use if the file cannot be read or
+    /// no tokens would be left.
+    pub fn reload(&self) -> Result<(), anyhow::Error> {
+        let mut accepted = self.tokens.clone();
+        if let Some(path) = &self.token_file {
+            accepted.extend(read_token_file(path)?);
+        }
+        accepted.retain(|token| !token.is_empty());
+        if accepted.is_empty() {
+            return Err(anyhow!("no sync tokens configured"));
+        }
+        *self.accepted.write().unwrap() = accepted;
+        Ok(())
+    }
+
+    pub fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        let candidate = if self.header == http::header::AUTHORIZATION {
+            bearer_token(headers)
+        } else {
+            headers
+                .get(&self.header)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+        };
+        candidate.is_some_and(|candidate| is_accepted(candidate, &self.accepted.read().unwrap()))
+    }
+}
+
+/// One token per line. Blank lines and lines starting with `#` are ignored.
+fn read_token_file(path: &Utf8Path) -> Result<Vec<String>, anyhow::Error> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow!("could not read token file \"{}\", {}", path, e))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Reloads the sync tokens every time the process receives SIGHUP.
+#[cfg(unix)]
+pub fn reload_on_sighup(auth: Arc<SyncAuth>, log: Logger) -> Result<(), std::io::Error> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match auth.reload() {
+                Ok(()) => info!(log, "reloaded sync tokens"),
+                Err(e) => error!(
+                    log,
+                    "failed to reload sync tokens, keeping the current tokens";
+                    "error" => format!("{e:#}"),
+                ),
+            }
+        }
+    });
+    Ok(())
+}
+
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Compares the candidate against every accepted token so that the time taken does not reveal
+/// which token, or how much of it, matched.
+pub(crate) fn is_accepted(candidate: &str, accepted: &[String]) -> bool {
+    accepted.iter().fold(false, |found, token| {
+        constant_time_eq(candidate.as_bytes(), token.as_bytes()) | found
+    })
+}
+
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    left.iter()
+        .zip(right)
+        .fold(0u8, |diff, (l, r)| diff | (l ^ r))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn reads_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(None, bearer_token(&headers));
+        headers.insert(
+            http::header::AUTHORIZATION,
+            HeaderValue::from_static("Basic dXNlcjpwYXNz"),
+        );
+        assert_eq!(None, bearer_token(&headers));
+        headers.insert(
+            http::header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer s3cr3t"),
+        );
+        assert_eq!(Some("s3cr3t"), bearer_token(&headers));
+    }
+
+    fn sync_auth_config(header: &str, token_file: Option<Utf8PathBuf>) -> SyncAuthConfig {
+        SyncAuthConfig {
+            header: header.to_string(),
+            token_file,
+            tokens: vec!["config-token".to_string()],
+        }
+    }
+
+    #[test]
+    fn checks_configured_header() {
+        let auth = SyncAuth::from_config(&sync_auth_config("X-Chimney-Token", None)).unwrap();
+        let mut headers = HeaderMap::new();
+        assert!(!auth.is_authorized(&headers));
+        headers.insert("x-chimney-token", HeaderValue::from_static("wrong-token"));
+        assert!(!auth.is_authorized(&headers));
+        headers.insert("x-chimney-token", HeaderValue::from_static("config-token"));
+        assert!(auth.is_authorized(&headers));
+
+        let auth = SyncAuth::from_config(&sync_auth_config("Authorization", None)).unwrap();
+        assert!(!auth.is_authorized(&headers));
+        headers.insert(
+            http::header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer config-token"),
+        );
+        assert!(auth.is_authorized(&headers));
+    }
+
+    #[test]
+    fn reloads_token_file() {
+        let path = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("chimney-reloads_token_file-{}", std::process::id()));
+        fs::write(&path, "# rotated monthly\nold-token\n\n").unwrap();
+        let auth = SyncAuth::from_config(&sync_auth_config("X-Chimney-Token", Some(path.clone())))
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-chimney-token", HeaderValue::from_static("old-token"));
+        assert!(auth.is_authorized(&headers));
+
+        fs::write(&path, "new-token\n").unwrap();
+        auth.reload().unwrap();
+        assert!(!auth.is_authorized(&headers));
+        headers.insert("x-chimney-token", HeaderValue::from_static("new-token"));
+        assert!(auth.is_authorized(&headers));
+
+        fs::remove_file(&path).unwrap();
+        assert!(auth.reload().is_err());
+        assert!(auth.is_authorized(&headers));
+    }
+
+    #[test]
+    fn accepts_any_configured_token() {
+        let accepted = vec!["old-token".to_string(), "new-token".to_string()];
+        assert!(is_accepted("old-token", &accepted));
+        assert!(is_accepted("new-token", &accepted));
+        assert!(!is_accepted("new-toke", &accepted));
+        assert!(!is_accepted("", &accepted));
+        assert!(!is_accepted("new-token", &[]));
+    }
+}
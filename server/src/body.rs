@@ -1,4 +1,7 @@
-use std::io::Read;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufReader, Read};
+use std::marker::PhantomData;
 
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -9,20 +12,109 @@ use dropshot::{
 use flate2::read::{MultiGzDecoder, ZlibDecoder};
 use hyper::Request;
 use schemars::JsonSchema;
-use serde::de::DeserializeOwned;
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::METRICS;
+use crate::{Context, DEFAULT_REQUEST_BODY_MAX_BYTES};
 
-const CONTENT_ENCODING_DEFLATE: &str = "deflate";
-const CONTENT_ENCODING_GZIP: &str = "gzip";
 const CONTENT_ENCODING_NONE: &str = "none";
+#[cfg(feature = "br")]
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// Limits on the size of a decoded request body. The request body size limit only applies to the
+/// compressed body, so these keep a small compressed body from expanding without bound.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DecodeLimit {
+    pub max_compression_ratio: Option<usize>,
+    pub max_decoded_bytes: Option<usize>,
+}
+
+impl DecodeLimit {
+    /// The largest decoded size allowed for a body of `encoded_len` bytes.
+    pub fn max_decoded_len(&self, encoded_len: usize) -> usize {
+        let max = self
+            .max_decoded_bytes
+            .unwrap_or(DEFAULT_REQUEST_BODY_MAX_BYTES);
+        match self.max_compression_ratio {
+            Some(ratio) => max.min(encoded_len.saturating_mul(ratio)),
+            None => max,
+        }
+    }
+}
+
+/// Decode limits for every endpoint, with overrides keyed by the endpoint's first path segment,
+/// e.g. `eventupload`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct DecodeLimits {
+    #[serde(flatten)]
+    pub default: DecodeLimit,
+    #[serde(default)]
+    pub endpoints: BTreeMap<String, DecodeLimit>,
+}
+
+impl DecodeLimits {
+    pub fn for_endpoint(&self, endpoint: &str) -> DecodeLimit {
+        let Some(limit) = self.endpoints.get(endpoint) else {
+            return self.default.clone();
+        };
+        DecodeLimit {
+            max_compression_ratio: limit
+                .max_compression_ratio
+                .or(self.default.max_compression_ratio),
+            max_decoded_bytes: limit.max_decoded_bytes.or(self.default.max_decoded_bytes),
+        }
+    }
+}
+
+/// A server context that sets the decode limits for request bodies.
+pub trait DecodeLimitsProvider {
+    fn decode_limits(&self) -> &DecodeLimits;
+}
+
+impl DecodeLimitsProvider for DecodeLimits {
+    fn decode_limits(&self) -> &DecodeLimits {
+        self
+    }
+}
 
+impl DecodeLimitsProvider for Context {
+    fn decode_limits(&self) -> &DecodeLimits {
+        &self.decode_limits
+    }
+}
+
+/// A JSON request body with optional content codings. The body is not read until the handler
+/// calls `read`, so a handler can reject a request, e.g. an unauthorized one, before any of its
+/// body is received or decompressed.
 #[derive(Debug)]
 pub struct CompressedTypedBody<BodyType: JsonSchema + DeserializeOwned + Send + Sync> {
-    inner: BodyType,
+    request: Request<Body>,
+    body_type: PhantomData<fn() -> BodyType>,
 }
 
-impl<BodyType: JsonSchema + DeserializeOwned + Send + Sync> CompressedTypedBody<BodyType> {
-    pub fn into_inner(self) -> BodyType {
-        self.inner
+impl<BodyType> CompressedTypedBody<BodyType>
+where
+    BodyType: JsonSchema + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Reads the body, removes its content codings, and deserializes it.
+    pub async fn read<C>(self, rqctx: &RequestContext<C>) -> Result<BodyType, HttpError>
+    where
+        C: DecodeLimitsProvider + ServerContext,
+    {
+        let (parts, body) = self.request.into_parts();
+        let request = Request::from_parts(parts.clone(), body);
+        let untyped_body = UntypedBody::from_request(rqctx, request).await?;
+
+        let content_encoding = content_encoding(&parts.headers)?;
+        let limit = decode_limit(rqctx);
+        let decoded_body = decode_body(content_encoding, untyped_body.as_bytes(), &limit)?;
+        let request = Request::from_parts(parts, Body::with_content(decoded_body));
+        let typed_body = TypedBody::<BodyType>::from_request(rqctx, request).await?;
+        Ok(typed_body.into_inner())
     }
 }
 
@@ -32,29 +124,12 @@ where
     BodyType: JsonSchema + DeserializeOwned + Send + Sync + 'static,
 {
     async fn from_request<Context: ServerContext>(
-        rqctx: &RequestContext<Context>,
+        _rqctx: &RequestContext<Context>,
         request: Request<Body>,
     ) -> Result<CompressedTypedBody<BodyType>, HttpError> {
-        let (parts, body) = request.into_parts();
-        let request = Request::from_parts(parts.clone(), body);
-        let untyped_body = UntypedBody::from_request(rqctx, request).await?;
-
-        let content_encoding = parts
-            .headers
-            .get(http::header::CONTENT_ENCODING)
-            .map(|value| {
-                value.to_str().map_err(|e| {
-                    HttpError::for_bad_request(None, format!("invalid content-encoding: {e}"))
-                })
-            })
-            .unwrap_or(Ok(CONTENT_ENCODING_NONE))?;
-
-        let decoded_body = decode_body(content_encoding, untyped_body.as_bytes())?;
-        let request = Request::from_parts(parts, Body::with_content(decoded_body));
-        let typed_body = TypedBody::<BodyType>::from_request(rqctx, request).await?;
-
         Ok(Self {
-            inner: typed_body.into_inner(),
+            request,
+            body_type: PhantomData,
         })
     }
 
@@ -63,38 +138,502 @@ where
     }
 }
 
-/// Santa client sends zlib-wrapped request data despite using Content-Encoding: deflate
-fn decode_body(content_encoding: &str, body: &[u8]) -> Result<Bytes, HttpError> {
-    match content_encoding {
-        CONTENT_ENCODING_DEFLATE => {
-            let mut zlib = ZlibDecoder::new(body);
-            let mut decoded = vec![];
-            zlib.read_to_end(&mut decoded).map_err(|e| {
-                HttpError::for_client_error(
+/// An event upload body that is decoded and deserialized one event at a time as the handler
+/// consumes it. Only the compressed body is buffered, so memory use does not grow with the number
+/// of events in the upload. Like `CompressedTypedBody`, the body is not read until the handler
+/// asks for its events.
+#[derive(Debug)]
+pub struct EventUploadBody {
+    request: Request<Body>,
+}
+
+impl EventUploadBody {
+    /// Reads the body and calls `f` with every event in the upload. The whole upload is decoded
+    /// and parsed before `f` is called, so `f` is not called at all when the upload is rejected.
+    /// The parsed events are held in memory until then, which the decoded size limit bounds.
+    pub async fn for_each_event<C, F>(
+        self,
+        rqctx: &RequestContext<C>,
+        f: F,
+    ) -> Result<(), HttpError>
+    where
+        C: DecodeLimitsProvider + ServerContext,
+        F: FnMut(santa_types::Event),
+    {
+        let codings = parse_content_encoding(content_encoding(self.request.headers())?)?;
+        let limit = decode_limit(rqctx);
+        let body = UntypedBody::from_request(rqctx, self.request).await?;
+        let max_len = limit.max_decoded_len(body.as_bytes().len());
+        let mut events = vec![];
+        parse_events(body.as_bytes(), &codings, max_len, |event| {
+            events.push(event)
+        })?;
+        events.into_iter().for_each(f);
+        Ok(())
+    }
+}
+
+/// Decodes `body` and calls `f` with each event as soon as it is parsed.
+fn parse_events<F: FnMut(santa_types::Event)>(
+    body: &[u8],
+    codings: &[ContentCoding],
+    max_len: usize,
+    f: F,
+) -> Result<(), HttpError> {
+    let mut reader: Box<dyn Read + '_> = Box::new(body);
+    // The last coding applied is the first one to remove.
+    for coding in codings.iter().rev() {
+        reader = Box::new(CodingReader {
+            inner: coding
+                .decoder(reader)
+                .map_err(|e| decode_error(*coding, e))?,
+            coding: *coding,
+        });
+    }
+    let reader = BufReader::new(LimitedReader {
+        inner: reader,
+        remaining: max_len,
+    });
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    EventUploadSeed(f)
+        .deserialize(&mut deserializer)
+        .and_then(|()| deserializer.end())
+        .map_err(|e| {
+            if !e.is_io() {
+                return HttpError::for_bad_request(None, format!("unable to parse JSON body: {e}"));
+            }
+            let e = io::Error::from(e);
+            if e.get_ref().is_some_and(|e| e.is::<DecodedBodyTooLarge>()) {
+                return HttpError::for_client_error(
                     None,
-                    ClientErrorStatusCode::UNSUPPORTED_MEDIA_TYPE,
-                    format!("not a zlib request body: {e}"),
-                )
-            })?;
-            Ok(Bytes::from(decoded))
+                    ClientErrorStatusCode::PAYLOAD_TOO_LARGE,
+                    format!("decoded request body exceeds {max_len} bytes"),
+                );
+            }
+            match e.downcast::<DecodeFailed>() {
+                Ok(failed) => decode_error(failed.coding, failed.source),
+                Err(e) => {
+                    HttpError::for_bad_request(None, format!("unable to read request body: {e}"))
+                }
+            }
+        })
+}
+
+#[async_trait]
+impl ExclusiveExtractor for EventUploadBody {
+    async fn from_request<Context: ServerContext>(
+        _rqctx: &RequestContext<Context>,
+        request: Request<Body>,
+    ) -> Result<EventUploadBody, HttpError> {
+        Ok(EventUploadBody { request })
+    }
+
+    fn metadata(content_type: ApiEndpointBodyContentType) -> ExtractorMetadata {
+        TypedBody::<santa_types::EventUploadOptions>::metadata(content_type)
+    }
+}
+
+/// Deserializes `EventUploadOptions` without collecting its events.
+struct EventUploadSeed<F>(F);
+
+impl<'de, F: FnMut(santa_types::Event)> DeserializeSeed<'de> for EventUploadSeed<F> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F: FnMut(santa_types::Event)> Visitor<'de> for EventUploadSeed<F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("struct EventUploadOptions")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "events" {
+                map.next_value_seed(EventsSeed(&mut self.0))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
         }
-        CONTENT_ENCODING_GZIP => {
-            let mut gz = MultiGzDecoder::new(body);
-            let mut decoded = vec![];
-            gz.read_to_end(&mut decoded).map_err(|e| {
-                HttpError::for_client_error(
+        Ok(())
+    }
+}
+
+struct EventsSeed<'f, F>(&'f mut F);
+
+impl<'de, F: FnMut(santa_types::Event)> DeserializeSeed<'de> for EventsSeed<'_, F> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(santa_types::Event)> Visitor<'de> for EventsSeed<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence of events")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(event) = seq.next_element()? {
+            (self.0)(event);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct DecodedBodyTooLarge;
+
+impl fmt::Display for DecodedBodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("decoded request body is too large")
+    }
+}
+
+impl std::error::Error for DecodedBodyTooLarge {}
+
+/// A content coding that could not be removed from a request body.
+#[derive(Debug)]
+struct DecodeFailed {
+    coding: ContentCoding,
+    source: io::Error,
+}
+
+impl fmt::Display for DecodeFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.coding.name(), self.source)
+    }
+}
+
+impl std::error::Error for DecodeFailed {}
+
+/// Tags errors from the decoder for `coding` with `DecodeFailed`, so a failure in a chain of
+/// codings can be traced to the coding that failed. Errors that are already tagged by a decoder
+/// further down the chain are passed on as they are.
+struct CodingReader<R> {
+    inner: R,
+    coding: ContentCoding,
+}
+
+impl<R: Read> Read for CodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|e| {
+            if e.get_ref().is_some_and(|e| e.is::<DecodeFailed>()) {
+                return e;
+            }
+            io::Error::other(DecodeFailed {
+                coding: self.coding,
+                source: e,
+            })
+        })
+    }
+}
+
+/// Fails with `DecodedBodyTooLarge` once more than `remaining` bytes have been read.
+struct LimitedReader<R> {
+    inner: R,
+    remaining: usize,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            let mut probe = [0u8; 1];
+            return match self.inner.read(&mut probe)? {
+                0 => Ok(0),
+                _ => Err(io::Error::other(DecodedBodyTooLarge)),
+            };
+        }
+        let len = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..len])?;
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+fn content_encoding(headers: &http::HeaderMap) -> Result<&str, HttpError> {
+    headers
+        .get(http::header::CONTENT_ENCODING)
+        .map(|value| {
+            value.to_str().map_err(|e| {
+                HttpError::for_bad_request(None, format!("invalid content-encoding: {e}"))
+            })
+        })
+        .unwrap_or(Ok(CONTENT_ENCODING_NONE))
+}
+
+/// A content coding from the `Content-Encoding` header. `identity` is accepted but needs no
+/// decoding, so it has no variant.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ContentCoding {
+    #[cfg(feature = "br")]
+    Brotli,
+    /// Santa client sends zlib-wrapped request data despite using Content-Encoding: deflate
+    Deflate,
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl ContentCoding {
+    fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "br")]
+            ContentCoding::Brotli => "br",
+            ContentCoding::Deflate => "deflate",
+            ContentCoding::Gzip => "gzip",
+            #[cfg(feature = "zstd")]
+            ContentCoding::Zstd => "zstd",
+        }
+    }
+
+    fn format_name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "br")]
+            ContentCoding::Brotli => "brotli",
+            ContentCoding::Deflate => "zlib",
+            ContentCoding::Gzip => "gzip",
+            #[cfg(feature = "zstd")]
+            ContentCoding::Zstd => "zstd",
+        }
+    }
+
+    fn decoder<'a, R: Read + 'a>(&self, reader: R) -> Result<Box<dyn Read + 'a>, io::Error> {
+        Ok(match self {
+            #[cfg(feature = "br")]
+            ContentCoding::Brotli => {
+                Box::new(brotli::Decompressor::new(reader, BROTLI_BUFFER_SIZE))
+            }
+            ContentCoding::Deflate => Box::new(ZlibDecoder::new(reader)),
+            ContentCoding::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            #[cfg(feature = "zstd")]
+            ContentCoding::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        })
+    }
+}
+
+/// Parses a `Content-Encoding` header into the codings that were applied, in the order they were
+/// applied (RFC 9110, section 8.4).
+fn parse_content_encoding(content_encoding: &str) -> Result<Vec<ContentCoding>, HttpError> {
+    let mut codings = vec![];
+    for coding in content_encoding.split(',').map(str::trim) {
+        let coding = match coding.to_ascii_lowercase().as_str() {
+            "" | "identity" | CONTENT_ENCODING_NONE => continue,
+            #[cfg(feature = "br")]
+            "br" => ContentCoding::Brotli,
+            "deflate" => ContentCoding::Deflate,
+            "gzip" | "x-gzip" => ContentCoding::Gzip,
+            #[cfg(feature = "zstd")]
+            "zstd" => ContentCoding::Zstd,
+            _ => {
+                METRICS
+                    .decompression_failures
+                    .with_label_values(&["unsupported"])
+                    .inc();
+                return Err(HttpError::for_client_error(
                     None,
                     ClientErrorStatusCode::UNSUPPORTED_MEDIA_TYPE,
-                    format!("not a gzip request body: {e}"),
-                )
-            })?;
-            Ok(Bytes::from(decoded))
-        }
-        CONTENT_ENCODING_NONE => Ok(Bytes::copy_from_slice(body)),
-        _ => Err(HttpError::for_client_error(
+                    format!("unsupported content-encoding \"{coding}\""),
+                ));
+            }
+        };
+        codings.push(coding);
+    }
+    Ok(codings)
+}
+
+fn decode_limit<C: DecodeLimitsProvider + ServerContext>(rqctx: &RequestContext<C>) -> DecodeLimit {
+    let endpoint = rqctx
+        .request
+        .uri()
+        .path()
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    rqctx.context().decode_limits().for_endpoint(endpoint)
+}
+
+fn decode_body(
+    content_encoding: &str,
+    body: &[u8],
+    limit: &DecodeLimit,
+) -> Result<Bytes, HttpError> {
+    let codings = parse_content_encoding(content_encoding)?;
+    let max_len = limit.max_decoded_len(body.len());
+    // An uncompressed body is held to the same limit as a decoded one.
+    if codings.is_empty() && body.len() > max_len {
+        return Err(HttpError::for_client_error(
             None,
-            ClientErrorStatusCode::UNSUPPORTED_MEDIA_TYPE,
-            format!("unsupported content-encoding \"{content_encoding}\""),
-        )),
+            ClientErrorStatusCode::PAYLOAD_TOO_LARGE,
+            format!("decoded request body exceeds {max_len} bytes"),
+        ));
+    }
+    let mut decoded = Bytes::copy_from_slice(body);
+    // The last coding applied is the first one to remove.
+    for coding in codings.iter().rev() {
+        decoded = decode(*coding, &decoded, max_len).map_err(|e| decode_error(*coding, e))?;
+        if decoded.len() > max_len {
+            return Err(HttpError::for_client_error(
+                None,
+                ClientErrorStatusCode::PAYLOAD_TOO_LARGE,
+                format!("decoded request body exceeds {max_len} bytes"),
+            ));
+        }
+    }
+    Ok(decoded)
+}
+
+/// The coding is supported but the body is not valid data for it. Failures are counted by the
+/// coding that failed, never by the whole chain, which the client controls.
+fn decode_error(coding: ContentCoding, error: impl fmt::Display) -> HttpError {
+    METRICS
+        .decompression_failures
+        .with_label_values(&[coding.name()])
+        .inc();
+    HttpError::for_bad_request(
+        None,
+        format!("not a {} request body: {error}", coding.format_name()),
+    )
+}
+
+/// Reads at most one byte past `max_len`, which is enough to tell that the limit was exceeded.
+fn decode(coding: ContentCoding, body: &[u8], max_len: usize) -> Result<Bytes, io::Error> {
+    let mut decoded = vec![];
+    coding
+        .decoder(body)?
+        .take(max_len as u64 + 1)
+        .read_to_end(&mut decoded)?;
+    Ok(Bytes::from(decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_content_encoding_chains() {
+        assert_eq!(
+            vec![ContentCoding::Gzip, ContentCoding::Deflate],
+            parse_content_encoding("gzip, identity,DEFLATE").unwrap()
+        );
+        assert!(parse_content_encoding("identity").unwrap().is_empty());
+        assert!(parse_content_encoding("").unwrap().is_empty());
+        let err = parse_content_encoding("gzip, compress").unwrap_err();
+        assert_eq!(
+            "unsupported content-encoding \"compress\"",
+            err.external_message
+        );
+    }
+
+    #[test]
+    fn merges_endpoint_decode_limits() {
+        let limits = DecodeLimits {
+            default: DecodeLimit {
+                max_compression_ratio: Some(50),
+                max_decoded_bytes: Some(1_000),
+            },
+            endpoints: BTreeMap::from([(
+                "eventupload".to_string(),
+                DecodeLimit {
+                    max_compression_ratio: None,
+                    max_decoded_bytes: Some(5_000),
+                },
+            )]),
+        };
+        let limit = limits.for_endpoint("eventupload");
+        assert_eq!(Some(50), limit.max_compression_ratio);
+        assert_eq!(Some(5_000), limit.max_decoded_bytes);
+        assert_eq!(limits.default, limits.for_endpoint("preflight"));
+        assert_eq!(500, limit.max_decoded_len(10));
+        assert_eq!(5_000, limit.max_decoded_len(1_000));
+    }
+
+    #[test]
+    fn rejects_decoded_body_over_limit() {
+        let mut zlib = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        std::io::Write::write_all(&mut zlib, &[0u8; 10_000]).unwrap();
+        let encoded = zlib.finish().unwrap();
+        let limit = DecodeLimit {
+            max_compression_ratio: None,
+            max_decoded_bytes: Some(10_000),
+        };
+        assert_eq!(
+            10_000,
+            decode_body("deflate", &encoded, &limit).unwrap().len()
+        );
+        let limit = DecodeLimit {
+            max_compression_ratio: None,
+            max_decoded_bytes: Some(9_999),
+        };
+        let err = decode_body("deflate", &encoded, &limit).unwrap_err();
+        assert_eq!(
+            http::StatusCode::PAYLOAD_TOO_LARGE,
+            err.status_code.as_status()
+        );
+        let limit = DecodeLimit {
+            max_compression_ratio: Some(2),
+            max_decoded_bytes: None,
+        };
+        assert!(decode_body("deflate", &encoded, &limit).is_err());
+        let limit = DecodeLimit {
+            max_compression_ratio: None,
+            max_decoded_bytes: Some(9_999),
+        };
+        let err = decode_body("identity", &[0u8; 10_000], &limit).unwrap_err();
+        assert_eq!(
+            http::StatusCode::PAYLOAD_TOO_LARGE,
+            err.status_code.as_status()
+        );
+    }
+
+    #[test]
+    fn streams_events_within_limit() {
+        let event = r#"{"file_sha256": "a", "file_path": "b", "file_name": "c", "decision": "ALLOW_BINARY"}"#;
+        let json = format!(r#"{{"extra": {{"ignored": [1, 2]}}, "events": [{event}, {event}]}}"#);
+        let mut count = 0;
+        let mut deserializer = serde_json::Deserializer::from_reader(LimitedReader {
+            inner: json.as_bytes(),
+            remaining: json.len(),
+        });
+        EventUploadSeed(|_| count += 1)
+            .deserialize(&mut deserializer)
+            .unwrap();
+        deserializer.end().unwrap();
+        assert_eq!(2, count);
+
+        let mut deserializer = serde_json::Deserializer::from_reader(LimitedReader {
+            inner: json.as_bytes(),
+            remaining: json.len() - 1,
+        });
+        let err = EventUploadSeed(|_| {})
+            .deserialize(&mut deserializer)
+            .unwrap_err();
+        let err = io::Error::from(err);
+        assert!(err.get_ref().unwrap().is::<DecodedBodyTooLarge>());
+    }
+
+    #[test]
+    fn reports_the_coding_that_failed_in_a_chain() {
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        // Gzip over bytes that are not zlib data.
+        std::io::Write::write_all(&mut gzip, b"not zlib").unwrap();
+        let encoded = gzip.finish().unwrap();
+        let codings = [ContentCoding::Deflate, ContentCoding::Gzip];
+
+        let err = parse_events(&encoded, &codings, usize::MAX, |_| {}).unwrap_err();
+        assert_eq!(http::StatusCode::BAD_REQUEST, err.status_code.as_status());
+        assert!(
+            err.external_message.starts_with("not a zlib request body"),
+            "{}",
+            err.external_message
+        );
     }
 }
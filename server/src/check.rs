@@ -0,0 +1,413 @@
+use std::fmt;
+use std::fs;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use toml::Spanned;
+
+use crate::ServerConfig;
+use crate::config_store::{
+    ClientConfig, ClientConfigMode, ClientConfigOverlay, ConfigFormat, ConfigStore,
+    ConfigStoreOptions, GLOBAL, GROUP, IdentifierValidation, PreflightOverrides, RuleSet, Selector,
+    find_config_files, is_ruleset_file, validate_identifier,
+};
+
+/// A problem with a configuration file.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Problem {
+    pub path: Utf8PathBuf,
+    /// The line the problem is on, when it can be pinned to one.
+    pub line: Option<usize>,
+    pub message: String,
+    pub severity: Severity,
+}
+
+/// Whether the server would refuse to load the configuration because of a problem.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    /// The server loads the configuration and logs the problem.
+    Warning,
+}
+
+impl Problem {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.severity == Severity::Warning {
+            f.write_str("warning: ")?;
+        }
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path, line, self.message),
+            None => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+/// The parts of a client config that are not flattened, so that errors in them point at a line.
+/// Deserializing a flattened struct loses the position of the error.
+#[derive(Deserialize)]
+struct Unflattened {
+    #[serde(default, rename = "rules")]
+    _rules: Vec<santa_types::Rule>,
+    #[serde(default, rename = "selector")]
+    _selector: Option<Selector>,
+    #[serde(default, rename = "include_rulesets")]
+    _include_rulesets: Vec<String>,
+}
+
+/// The values in a client config that are checked beyond what deserializing it checks. In TOML
+/// files, each value is read with its position in the file.
+#[derive(Deserialize)]
+struct Spans<S> {
+    allowed_path_regex: Option<S>,
+    blocked_path_regex: Option<S>,
+    #[serde(default = "Vec::new")]
+    rules: Vec<SpannedRule<S>>,
+}
+
+#[derive(Deserialize)]
+struct SpannedRule<S> {
+    identifier: S,
+    rule_type: santa_types::RuleType,
+}
+
+/// A string read from a client config, and the line it is on if that is known.
+trait Located {
+    fn value(&self) -> &str;
+    fn line(&self, contents: &str) -> Option<usize>;
+}
+
+impl Located for String {
+    fn value(&self) -> &str {
+        self
+    }
+
+    fn line(&self, _contents: &str) -> Option<usize> {
+        None
+    }
+}
+
+impl Located for Spanned<String> {
+    fn value(&self) -> &str {
+        self.get_ref()
+    }
+
+    fn line(&self, contents: &str) -> Option<usize> {
+        Some(line_of(contents, self.span().start))
+    }
+}
+
+/// Checks the server config and every client config it points to, without starting the server.
+/// Returns every problem found, or an error if there is no server config to check.
+pub fn check<P: AsRef<Utf8Path>>(maybe_path: Option<P>) -> Result<Vec<Problem>, anyhow::Error> {
+    let path = ServerConfig::path(maybe_path)?;
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => return Ok(vec![problem(&path, None, e)]),
+    };
+    let config: ServerConfig = match parse(ConfigFormat::Toml, &path, &contents) {
+        Ok(config) => config,
+        Err(problem) => return Ok(vec![problem]),
+    };
+    let mut problems = vec![];
+    if let Err(e) = config.validate() {
+        problems.push(problem(&path, None, e));
+    }
+    let options = ConfigStoreOptions {
+        mode: config.client_config_mode,
+        identifier_validation: config.rule_identifier_validation,
+    };
+    problems.extend(check_client_configs(&config.client_config_path, &options));
+    Ok(problems)
+}
+
+/// Checks every client config under `path`, then loads them together the way the server does to
+/// find problems that span files, like a missing `global.toml` or an unknown group. Files that are
+/// not in a client config format are skipped, the same as when the server loads them. Malformed
+/// rule identifiers are warnings when `options` would have the server serve them anyway.
+pub fn check_client_configs(path: &Utf8Path, options: &ConfigStoreOptions) -> Vec<Problem> {
+    let validation = options.identifier_validation;
+    let files = match find_config_files(path) {
+        Ok(files) => files,
+        Err(e) => return vec![problem(path, None, e)],
+    };
+    let mut problems: Vec<Problem> = files
+        .iter()
+        .filter_map(|file| Some((file, ConfigFormat::from_path(file)?)))
+        .flat_map(|(file, format)| match is_ruleset_file(path, file) {
+            true => check_ruleset(file, format, validation),
+            false => check_client_config(file, format, options.mode, validation),
+        })
+        .collect();
+    if !problems.iter().any(Problem::is_error)
+        && let Err(e) = ConfigStore::from_path_with_options(path, options.clone())
+    {
+        problems.push(problem(path, None, e));
+    }
+    problems.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+    problems
+}
+
+fn check_client_config(
+    file: &Utf8Path,
+    format: ConfigFormat,
+    mode: ClientConfigMode,
+    validation: IdentifierValidation,
+) -> Vec<Problem> {
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(e) => return vec![problem(file, None, e)],
+    };
+    // Parse the parts of the file that can point at the line of an error before the whole file.
+    let overlay = match (mode, file.file_stem()) {
+        (ClientConfigMode::Replace, Some(GROUP)) => {
+            let message = "group config requires client_config_mode = \"merge\"";
+            return vec![problem(file, None, message)];
+        }
+        (ClientConfigMode::Merge, Some(stem)) => stem != GLOBAL,
+        _ => false,
+    };
+    let parse_staged = |contents: &str| {
+        parse::<IgnoredAny>(format, file, contents)
+            .and_then(|_| match overlay {
+                true => parse::<PreflightOverrides>(format, file, contents).map(|_| ()),
+                false => parse::<santa_types::Preflight>(format, file, contents).map(|_| ()),
+            })
+            .and_then(|()| parse::<Unflattened>(format, file, contents))
+            .and_then(|_| match overlay {
+                true => parse::<ClientConfigOverlay>(format, file, contents).map(|_| ()),
+                false => parse::<ClientConfig>(format, file, contents).map(|_| ()),
+            })
+    };
+    if let Err(problem) = parse_staged(&contents) {
+        return vec![problem];
+    }
+    check_values(format, file, &contents, validation).unwrap_or_else(|problem| vec![problem])
+}
+
+fn check_ruleset(
+    file: &Utf8Path,
+    format: ConfigFormat,
+    validation: IdentifierValidation,
+) -> Vec<Problem> {
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(e) => return vec![problem(file, None, e)],
+    };
+    parse::<RuleSet>(format, file, &contents)
+        .and_then(|_| check_values(format, file, &contents, validation))
+        .unwrap_or_else(|problem| vec![problem])
+}
+
+/// Checks the regexes and rule identifiers in a file that has already been parsed, pointing at
+/// their lines in TOML files.
+fn check_values(
+    format: ConfigFormat,
+    file: &Utf8Path,
+    contents: &str,
+    validation: IdentifierValidation,
+) -> Result<Vec<Problem>, Problem> {
+    Ok(match format {
+        ConfigFormat::Toml => {
+            let spans = parse::<Spans<Spanned<String>>>(format, file, contents)?;
+            check_spans(file, contents, &spans, validation)
+        }
+        _ => check_spans(
+            file,
+            contents,
+            &parse::<Spans<String>>(format, file, contents)?,
+            validation,
+        ),
+    })
+}
+
+fn check_spans<S: Located>(
+    file: &Utf8Path,
+    contents: &str,
+    spans: &Spans<S>,
+    validation: IdentifierValidation,
+) -> Vec<Problem> {
+    let mut problems = vec![];
+    let regexes = [
+        ("allowed_path_regex", &spans.allowed_path_regex),
+        ("blocked_path_regex", &spans.blocked_path_regex),
+    ];
+    for (key, regex) in regexes {
+        // Santa compiles these with ICU, whose syntax differs from fancy_regex in places, so a
+        // pattern fancy_regex rejects may still work and only gets a warning.
+        if let Some(regex) = regex
+            && let Err(e) = fancy_regex::Regex::new(regex.value())
+        {
+            let line = regex.line(contents);
+            let message = format!(
+                "{key} may be invalid: {e} (checked with fancy_regex, Santa uses ICU regular \
+                 expressions)"
+            );
+            let mut problem = problem(file, line, message);
+            problem.severity = Severity::Warning;
+            problems.push(problem);
+        }
+    }
+    for rule in &spans.rules {
+        if let Err(e) = validate_identifier(&rule.rule_type, rule.identifier.value()) {
+            let mut problem = problem(file, rule.identifier.line(contents), e);
+            if validation == IdentifierValidation::Warn {
+                problem.severity = Severity::Warning;
+            }
+            problems.push(problem);
+        }
+    }
+    problems
+}
+
+fn parse<T: DeserializeOwned>(
+    format: ConfigFormat,
+    path: &Utf8Path,
+    contents: &str,
+) -> Result<T, Problem> {
+    match format {
+        ConfigFormat::Json => serde_json::from_str(contents).map_err(|e| {
+            let line = Some(e.line()).filter(|line| *line > 0);
+            problem(path, line, without_position(&e.to_string()))
+        }),
+        ConfigFormat::Toml => toml::from_str(contents).map_err(|e| {
+            let line = e.span().map(|span| line_of(contents, span.start));
+            problem(path, line, e.message())
+        }),
+        ConfigFormat::Yaml => serde_norway::from_str(contents).map_err(|e| {
+            let line = e.location().map(|location| location.line());
+            problem(path, line, without_position(&e.to_string()))
+        }),
+    }
+}
+
+/// JSON and YAML errors end with the position of the error, which problems already start with.
+fn without_position(message: &str) -> &str {
+    match message.rfind(" at line ") {
+        Some(index) => &message[..index],
+        None => message,
+    }
+}
+
+pub(crate) fn problem(path: &Utf8Path, line: Option<usize>, message: impl fmt::Display) -> Problem {
+    Problem {
+        path: path.to_path_buf(),
+        line,
+        message: message.to_string(),
+        severity: Severity::Error,
+    }
+}
+
+/// The 1-based line number of a byte offset.
+fn line_of(contents: &str, offset: usize) -> usize {
+    contents[..offset.min(contents.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge() -> ConfigStoreOptions {
+        ConfigStoreOptions {
+            mode: ClientConfigMode::Merge,
+            ..ConfigStoreOptions::default()
+        }
+    }
+
+    #[test]
+    fn passes_valid_client_configs() {
+        let problems = check_client_configs(
+            Utf8Path::new("tests/tomls/client-tomls/good"),
+            &ConfigStoreOptions::default(),
+        );
+        assert!(problems.is_empty(), "{problems:?}");
+        let problems =
+            check_client_configs(Utf8Path::new("tests/tomls/client-tomls/groups"), &merge());
+        assert!(problems.is_empty(), "{problems:?}");
+        let problems = check_client_configs(
+            Utf8Path::new("tests/tomls/client-tomls/rulesets"),
+            &ConfigStoreOptions::default(),
+        );
+        assert!(problems.is_empty(), "{problems:?}");
+    }
+
+    #[test]
+    fn reports_every_problem_with_its_line() {
+        let options = ConfigStoreOptions {
+            identifier_validation: IdentifierValidation::Reject,
+            ..ConfigStoreOptions::default()
+        };
+        let problems =
+            check_client_configs(Utf8Path::new("tests/tomls/client-tomls/check"), &options);
+        let problems = problems.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(8, problems.len(), "{problems:?}");
+        assert!(problems[0].starts_with("tests/tomls/client-tomls/check/bad-enum.toml:5: "));
+        assert!(problems[0].contains("unknown variant `PARANOID`"));
+        assert!(problems[1].starts_with(
+            "warning: tests/tomls/client-tomls/check/bad-regex.yaml: blocked_path_regex may be invalid"
+        ));
+        assert!(problems[1].ends_with("Santa uses ICU regular expressions)"));
+        assert!(problems[2].starts_with("tests/tomls/client-tomls/check/bad-rule.json:7: "));
+        assert!(problems[2].contains("unknown variant `BLOCK`"));
+        assert!(problems[3].starts_with("tests/tomls/client-tomls/check/bad-rule.toml:13: "));
+        assert!(problems[3].contains("unknown variant `ALLOW`"));
+        assert!(problems[4].starts_with("warning: tests/tomls/client-tomls/check/global.toml:6: "));
+        assert!(problems[4].contains("allowed_path_regex may be invalid"));
+        assert_eq!(
+            "tests/tomls/client-tomls/check/global.toml:13: TEAMID identifier \"EQHXZ8M8A\" must be 10 letters and digits",
+            problems[5]
+        );
+        assert!(problems[6].starts_with("tests/tomls/client-tomls/check/not-toml.toml:1: "));
+        assert_eq!(
+            "tests/tomls/client-tomls/check/rulesets/bad.toml:4: CDHASH identifier \"not-a-cdhash\" must be 40 hexadecimal characters",
+            problems[7]
+        );
+    }
+
+    #[test]
+    fn reports_problems_across_files() {
+        let problems = check_client_configs(
+            Utf8Path::new("tests/tomls/client-tomls/bad-groups"),
+            &merge(),
+        );
+        assert_eq!(1, problems.len());
+        assert_eq!(None, problems[0].line);
+        assert!(
+            problems[0]
+                .message
+                .contains("unknown group \"nonexistent\"")
+        );
+
+        let problems = check_client_configs(
+            Utf8Path::new("tests/tomls/client-tomls/bad-rulesets"),
+            &ConfigStoreOptions::default(),
+        );
+        assert_eq!(1, problems.len());
+        assert!(problems[0].message.contains(
+            "client config \"tests/tomls/client-tomls/bad-rulesets/global.toml\" includes unknown rule set \"missing\""
+        ));
+    }
+
+    #[test]
+    fn warns_about_malformed_identifiers_the_server_would_serve() {
+        let path = Utf8Path::new("tests/tomls/client-tomls/bad-identifiers");
+        let problems = check_client_configs(path, &ConfigStoreOptions::default());
+        assert!(!problems.is_empty());
+        assert!(!problems.iter().any(Problem::is_error), "{problems:?}");
+        assert!(problems[0].to_string().starts_with("warning: "));
+
+        let options = ConfigStoreOptions {
+            identifier_validation: IdentifierValidation::Reject,
+            ..ConfigStoreOptions::default()
+        };
+        let problems = check_client_configs(path, &options);
+        assert!(!problems.is_empty());
+        assert!(problems.iter().all(Problem::is_error));
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use rustls::pki_types::CertificateDer;
+use slog::{Logger, info, warn};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// How long a client has to complete the TLS handshake before its connection is dropped.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The client certificate a sync connection was made with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientIdentity {
+    /// The subject common name and DNS subject alternative names of the certificate.
+    pub names: Vec<String>,
+    /// The address the client connected from.
+    pub remote_addr: SocketAddr,
+}
+
+impl ClientIdentity {
+    /// Reads the names of a client certificate that has already been verified against the client
+    /// CA. Fails on certificates that are not well formed DER.
+    pub fn from_certificate(
+        cert: &CertificateDer<'_>,
+        remote_addr: SocketAddr,
+    ) -> Result<Self, anyhow::Error> {
+        let (rest, cert) = X509Certificate::from_der(cert.as_ref())
+            .map_err(|e| anyhow!("invalid client certificate, {}", e))?;
+        if !rest.is_empty() {
+            return Err(anyhow!("invalid client certificate, trailing data"));
+        }
+        let mut names = vec![];
+        for name in cert.subject().iter_common_name() {
+            let name = name
+                .as_str()
+                .map_err(|e| anyhow!("invalid client certificate common name, {}", e))?;
+            names.push(name.to_string());
+        }
+        let alternative_names = cert
+            .subject_alternative_name()
+            .map_err(|e| anyhow!("invalid client certificate subject alternative name, {}", e))?;
+        for name in alternative_names
+            .iter()
+            .flat_map(|ext| &ext.value.general_names)
+        {
+            if let GeneralName::DNSName(name) = name
+                && !names.iter().any(|existing| existing == name)
+            {
+                names.push(name.to_string());
+            }
+        }
+        Ok(ClientIdentity { names, remote_addr })
+    }
+
+    /// Whether the certificate was issued for `machine_id`.
+    pub fn matches(&self, machine_id: &str) -> bool {
+        self.names.iter().any(|name| name == machine_id)
+    }
+}
+
+/// The client certificates of the connections forwarded to the sync server, keyed by the address
+/// the sync server sees for each connection.
+#[derive(Debug, Default)]
+pub struct ClientIdentities {
+    by_addr: Mutex<HashMap<SocketAddr, ClientIdentity>>,
+}
+
+impl ClientIdentities {
+    pub fn get(&self, addr: SocketAddr) -> Option<ClientIdentity> {
+        self.by_addr.lock().unwrap().get(&addr).cloned()
+    }
+
+    fn insert(&self, addr: SocketAddr, identity: ClientIdentity) {
+        self.by_addr.lock().unwrap().insert(addr, identity);
+    }
+
+    fn remove(&self, addr: SocketAddr) {
+        self.by_addr.lock().unwrap().remove(&addr);
+    }
+}
+
+/// Accepts TLS connections on `bind_address` and forwards each one to the sync server at
+/// `upstream` over a new local connection. The client certificate of every connection is kept in
+/// `identities` under the address of its local connection for as long as the connection is open,
+/// so sync requests can be checked against the certificate they arrived with. Returns the address
+/// the listener is bound to.
+pub async fn start_tls_listener(
+    bind_address: SocketAddr,
+    config: rustls::ServerConfig,
+    upstream: SocketAddr,
+    identities: Arc<ClientIdentities>,
+    log: Logger,
+) -> Result<SocketAddr, anyhow::Error> {
+    let listener = TcpListener::bind(bind_address).await?;
+    let local_addr = listener.local_addr()?;
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    info!(log, "listening for TLS connections"; "local_addr" => local_addr.to_string());
+    tokio::spawn(async move {
+        loop {
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!(log, "accept error"; "error" => e.to_string());
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let identities = identities.clone();
+            let log = log.clone();
+            tokio::spawn(async move {
+                if let Err(e) = forward(
+                    acceptor,
+                    stream,
+                    remote_addr,
+                    upstream,
+                    &identities,
+                    TLS_HANDSHAKE_TIMEOUT,
+                    &log,
+                )
+                .await
+                {
+                    warn!(
+                        log,
+                        "TLS connection failed";
+                        "remote_addr" => remote_addr.to_string(),
+                        "error" => e.to_string(),
+                    );
+                }
+            });
+        }
+    });
+    Ok(local_addr)
+}
+
+async fn forward(
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+    upstream: SocketAddr,
+    identities: &ClientIdentities,
+    handshake_timeout: Duration,
+    log: &Logger,
+) -> Result<(), std::io::Error> {
+    let mut stream = tokio::time::timeout(handshake_timeout, acceptor.accept(stream))
+        .await
+        .map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::TimedOut, "TLS handshake timed out")
+        })??;
+    // The client certificate verifier only completes the handshake with a certificate.
+    let identity = match stream.get_ref().1.peer_certificates() {
+        Some([cert, ..]) => {
+            ClientIdentity::from_certificate(cert, remote_addr).map_err(std::io::Error::other)?
+        }
+        _ => return Err(std::io::Error::other("no client certificate")),
+    };
+    let mut upstream = TcpStream::connect(upstream).await?;
+    let key = upstream.local_addr()?;
+    // The sync server logs requests under `upstream_addr`; this ties them back to the client.
+    info!(
+        log,
+        "forwarding TLS connection";
+        "remote_addr" => remote_addr.to_string(),
+        "upstream_addr" => key.to_string(),
+        "certificate_names" => identity.names.join(","),
+    );
+    identities.insert(key, identity);
+    let result = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+    identities.remove(key);
+    result.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_config::{TlsConfig, read_certs};
+
+    fn client_identity() -> ClientIdentity {
+        let certs = read_certs("tests/tls/client.pem".into()).unwrap();
+        ClientIdentity::from_certificate(&certs[0], "127.0.0.1:1234".parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn reads_common_name_and_dns_names() {
+        assert_eq!(vec!["machine-id-1234"], client_identity().names);
+    }
+
+    #[test]
+    fn matches_machine_ids_exactly() {
+        let identity = client_identity();
+        assert!(identity.matches("machine-id-1234"));
+        assert!(!identity.matches("machine-id-5678"));
+        assert!(!identity.matches("MACHINE-ID-1234"));
+        assert!(!identity.matches("machine-id-12345"));
+    }
+
+    #[test]
+    fn rejects_malformed_certificates() {
+        let addr = "127.0.0.1:1234".parse().unwrap();
+        let der = read_certs("tests/tls/client.pem".into()).unwrap().remove(0);
+        let truncated = CertificateDer::from(&der[..der.len() / 2]);
+        assert!(ClientIdentity::from_certificate(&truncated, addr).is_err());
+        let mut trailing = der.to_vec();
+        trailing.push(0);
+        let trailing = CertificateDer::from(trailing);
+        assert!(ClientIdentity::from_certificate(&trailing, addr).is_err());
+        let garbage = CertificateDer::from(&b"not a certificate"[..]);
+        assert!(ClientIdentity::from_certificate(&garbage, addr).is_err());
+        assert!(ClientIdentity::from_certificate(&CertificateDer::from(&[][..]), addr).is_err());
+    }
+
+    #[tokio::test]
+    async fn times_out_stalled_handshakes() {
+        let tls_config = TlsConfig {
+            cert_file: "tests/tls/server.pem".into(),
+            client_ca_file: Some("tests/tls/ca.pem".into()),
+            key_file: "tests/tls/server.key".into(),
+        };
+        let acceptor = TlsAcceptor::from(Arc::new(
+            tls_config.to_client_auth_config().unwrap().unwrap(),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // A client that connects and never starts the handshake.
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, remote_addr) = listener.accept().await.unwrap();
+        let log = Logger::root(slog::Discard, slog::o!());
+        let err = forward(
+            acceptor,
+            stream,
+            remote_addr,
+            listener.local_addr().unwrap(),
+            &ClientIdentities::default(),
+            Duration::from_millis(50),
+            &log,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(std::io::ErrorKind::TimedOut, err.kind());
+    }
+}
@@ -1,6 +1,9 @@
 use anyhow::anyhow;
 use camino::Utf8Path;
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use crate::config_store::Selector;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct ClientConfig {
@@ -8,25 +11,80 @@ pub struct ClientConfig {
     pub machine_id: String,
     #[serde(flatten)]
     pub preflight: santa_types::Preflight,
+    /// Rule sets whose rules come before the rules in this file. Their rules are already in
+    /// `rules` once the store is loaded.
+    #[serde(default)]
+    pub include_rulesets: Vec<String>,
     #[serde(default)]
     pub rules: Vec<santa_types::Rule>,
+    #[serde(default)]
+    pub selector: Option<Selector>,
 }
 
 impl ClientConfig {
     pub fn from_file<P: AsRef<Utf8Path>>(path: P) -> Result<Self, anyhow::Error> {
         let path = path.as_ref();
-        let contents = std::fs::read_to_string(path)?;
-        let mut config: Self = match toml::from_str(&contents) {
-            Ok(config) => config,
-            Err(e) => return Err(anyhow!("could not parse TOML \"{}\", {}", path, e,)),
-        };
-        let machine_id = path.file_stem().map(|f| f.to_string()).unwrap();
-        config.machine_id = machine_id;
+        let mut config: Self = read_config(path)?;
+        config.machine_id = machine_id_for(path);
 
         Ok(config)
     }
 }
 
+/// The formats client configs can be written in. The format of a file is chosen by its extension.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// The format of a file, or `None` if its extension is not one of `.json`, `.toml`, `.yaml` or
+    /// `.yml`.
+    pub fn from_path(path: &Utf8Path) -> Option<Self> {
+        match path.extension()?.to_ascii_lowercase().as_str() {
+            "json" => Some(ConfigFormat::Json),
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "JSON",
+            ConfigFormat::Toml => "TOML",
+            ConfigFormat::Yaml => "YAML",
+        }
+    }
+}
+
+pub(crate) fn read_config<T: DeserializeOwned>(path: &Utf8Path) -> Result<T, anyhow::Error> {
+    let format = ConfigFormat::from_path(path)
+        .ok_or_else(|| anyhow!("unknown client config format \"{}\"", path))?;
+    let contents = std::fs::read_to_string(path)?;
+    let parsed = match format {
+        ConfigFormat::Json => serde_json::from_str(&contents).map_err(anyhow::Error::from),
+        ConfigFormat::Toml => toml::from_str(&contents).map_err(anyhow::Error::from),
+        ConfigFormat::Yaml => serde_norway::from_str(&contents).map_err(anyhow::Error::from),
+    };
+    parsed.map_err(|e| anyhow!("could not parse {} \"{}\", {}", format.name(), path, e))
+}
+
+pub(crate) fn machine_id_for(path: &Utf8Path) -> String {
+    path.file_stem().map(|f| f.to_string()).unwrap()
+}
+
+/// Whether a machine id or group name can be used as a file name without escaping its directory.
+pub(crate) fn is_safe_file_stem(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -40,6 +98,21 @@ mod tests {
         assert_eq!(8, config.rules.len());
     }
 
+    #[test]
+    fn parses_config_files_by_extension() {
+        let toml = ClientConfig::from_file("tests/tomls/client-tomls/formats/global.toml").unwrap();
+        let json = ClientConfig::from_file("tests/tomls/client-tomls/formats/machine-id-1234.json")
+            .unwrap();
+        let yaml = ClientConfig::from_file("tests/tomls/client-tomls/formats/machine-id-5678.yaml")
+            .unwrap();
+        assert_eq!("machine-id-1234", json.machine_id);
+        assert_eq!("machine-id-5678", yaml.machine_id);
+        assert_eq!(toml.preflight, json.preflight);
+        assert_eq!(toml.preflight, yaml.preflight);
+        assert_eq!(toml.rules, json.rules);
+        assert_eq!(toml.rules, yaml.rules);
+    }
+
     #[test]
     fn gracefully_handles_a_bad_config_file() {
         let result = ClientConfig::from_file("tests/tomls/client-tomls/bad/bad.toml");
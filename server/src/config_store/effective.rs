@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::anyhow;
+use camino::Utf8PathBuf;
+use serde::Serialize;
+
+use crate::config_store::client_config::read_config;
+use crate::config_store::store::memberships;
+use crate::config_store::{
+    ClientConfigMode, ClientConfigOverlay, ConfigStore, GLOBAL, included_rules,
+};
+
+/// The preflight response and rules the server sends a machine, and the files they came from.
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfig {
+    /// The config the machine gets: its own, one picked by a selector, or global.
+    pub config: String,
+    pub preflight: santa_types::Preflight,
+    pub rules: Vec<santa_types::Rule>,
+    pub sources: EffectiveSources,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EffectiveSources {
+    /// The files the config was read from, in the order they are layered.
+    pub files: Vec<Utf8PathBuf>,
+    /// The last file to set each preflight setting. Settings that no file sets are left out.
+    pub preflight: BTreeMap<String, Utf8PathBuf>,
+    /// The file each rule came from, in the same order as the rules. Rules from a rule set point
+    /// at the rule set's file.
+    pub rules: Vec<Utf8PathBuf>,
+}
+
+impl ConfigStore {
+    /// The config the server sends `machine_id`. Configs with a selector are only considered when
+    /// the machine's preflight request is given.
+    ///
+    /// The files are read again to find where each setting came from, so they should not have
+    /// changed since the store was loaded.
+    pub fn effective(
+        &self,
+        machine_id: &str,
+        options: Option<&santa_types::PreflightOptions>,
+    ) -> Result<EffectiveConfig, anyhow::Error> {
+        let key = match options {
+            Some(options) => self.select(machine_id, options),
+            None if self.cache.contains_key(machine_id) => machine_id.to_string(),
+            None => GLOBAL.to_string(),
+        };
+        let config = &self.cache[&key];
+        let files = self.layers(&key)?;
+
+        let mut preflight_sources: BTreeMap<String, Utf8PathBuf> = BTreeMap::new();
+        let mut rules: Vec<santa_types::Rule> = vec![];
+        let mut rule_sources: Vec<Utf8PathBuf> = vec![];
+        let mut positions: HashMap<(santa_types::RuleType, String), usize> = HashMap::new();
+        for file in &files {
+            let mut table: serde_json::Map<String, serde_json::Value> = read_config(file)?;
+            for setting in table.keys() {
+                preflight_sources.insert(setting.clone(), file.clone());
+            }
+            let included: Vec<String> = match table.remove("include_rulesets") {
+                Some(included) => serde_json::from_value(included).map_err(|e| {
+                    anyhow!("could not parse include_rulesets in \"{}\", {}", file, e)
+                })?,
+                None => vec![],
+            };
+            let layer_rules: Vec<santa_types::Rule> = match table.remove("rules") {
+                Some(layer_rules) => serde_json::from_value(layer_rules)
+                    .map_err(|e| anyhow!("could not parse rules in \"{}\", {}", file, e))?,
+                None => vec![],
+            };
+            let includer = format!("client config \"{file}\"");
+            let included_rules = included_rules(&self.rulesets, &includer, &included)
+                .map_err(|e| anyhow!("{}", e))?
+                .into_iter()
+                .map(|(name, rule)| (&self.sources.rulesets[name], rule.clone()));
+            let layer_rules = layer_rules.into_iter().map(|rule| (file, rule));
+            for (source, rule) in included_rules.chain(layer_rules) {
+                let key = (rule.rule_type.clone(), rule.identifier.clone());
+                match positions.get(&key) {
+                    Some(&index) => {
+                        rules[index] = rule;
+                        rule_sources[index] = source.clone();
+                    }
+                    None => {
+                        positions.insert(key, rules.len());
+                        rules.push(rule);
+                        rule_sources.push(source.clone());
+                    }
+                }
+            }
+        }
+        let settings = serde_json::to_value(&config.preflight)?;
+        preflight_sources.retain(|setting, _| settings.get(setting).is_some());
+        if rules != config.rules {
+            return Err(anyhow!(
+                "client configs under {:?} changed since they were loaded",
+                self.path
+            ));
+        }
+
+        Ok(EffectiveConfig {
+            config: key,
+            preflight: config.preflight.clone(),
+            rules,
+            sources: EffectiveSources {
+                files,
+                preflight: preflight_sources,
+                rules: rule_sources,
+            },
+        })
+    }
+
+    /// The files a config is layered from: just its own file, unless client configs are merged.
+    fn layers(&self, key: &str) -> Result<Vec<Utf8PathBuf>, anyhow::Error> {
+        let file = &self.sources.configs[key];
+        if self.options.mode == ClientConfigMode::Replace || key == GLOBAL {
+            return Ok(vec![file.clone()]);
+        }
+        let overlay = ClientConfigOverlay::from_file(file)?;
+        let mut files = vec![self.sources.configs[GLOBAL].clone()];
+        files.extend(
+            memberships(&self.path, file, &overlay, &self.groups)
+                .iter()
+                .map(|group| self.sources.groups[group].clone()),
+        );
+        files.push(file.clone());
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_store::ConfigStoreOptions;
+
+    #[test]
+    fn reads_a_replaced_config_from_one_file() {
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/good").unwrap();
+        let effective = store.effective("machine-id-1234", None).unwrap();
+        let file = Utf8PathBuf::from("tests/tomls/client-tomls/good/machine-id-1234.toml");
+        assert_eq!("machine-id-1234", effective.config);
+        assert_eq!(vec![file.clone()], effective.sources.files);
+        assert_eq!(Some(&file), effective.sources.preflight.get("client_mode"));
+        assert_eq!(8, effective.rules.len());
+        assert_eq!(vec![file; 8], effective.sources.rules);
+
+        let effective = store.effective("nonexistent-machine-id", None).unwrap();
+        assert_eq!(GLOBAL, effective.config);
+    }
+
+    #[test]
+    fn traces_merged_settings_to_their_layer() {
+        let options = ConfigStoreOptions {
+            mode: ClientConfigMode::Merge,
+            ..ConfigStoreOptions::default()
+        };
+        let store = ConfigStore::from_path_with_options("tests/tomls/client-tomls/groups", options)
+            .unwrap();
+        let effective = store.effective("machine-id-1234", None).unwrap();
+        let global = Utf8PathBuf::from("tests/tomls/client-tomls/groups/global.toml");
+        let engineering =
+            Utf8PathBuf::from("tests/tomls/client-tomls/groups/engineering/group.toml");
+        let security = Utf8PathBuf::from("tests/tomls/client-tomls/groups/security/group.toml");
+        let machine =
+            Utf8PathBuf::from("tests/tomls/client-tomls/groups/engineering/machine-id-1234.toml");
+        assert_eq!(
+            vec![
+                global.clone(),
+                engineering.clone(),
+                security.clone(),
+                machine.clone()
+            ],
+            effective.sources.files
+        );
+        assert_eq!(store.config_for("machine-id-1234").rules, effective.rules);
+
+        let preflight = &effective.sources.preflight;
+        assert_eq!(Some(&global), preflight.get("batch_size"));
+        assert_eq!(Some(&engineering), preflight.get("full_sync_interval"));
+        assert_eq!(Some(&security), preflight.get("block_usb_mount"));
+        assert_eq!(Some(&machine), preflight.get("client_mode"));
+        assert_eq!(None, preflight.get("groups"));
+        assert_eq!(None, preflight.get("allowed_path_regex"));
+        assert_eq!(vec![global, engineering, machine], effective.sources.rules);
+    }
+
+    #[test]
+    fn considers_selectors_with_a_preflight_request() {
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/selectors").unwrap();
+        let options: santa_types::PreflightOptions = serde_json::from_value(serde_json::json!({
+            "serial_num": "C02SERIAL",
+            "hostname": "eng-1",
+            "os_version": "14.4.1",
+            "os_build": "23E224",
+            "santa_version": "2024.1",
+            "client_mode": "MONITOR",
+        }))
+        .unwrap();
+        assert_eq!(GLOBAL, store.effective("unknown", None).unwrap().config);
+        let effective = store.effective("unknown", Some(&options)).unwrap();
+        assert_eq!("by-serial", effective.config);
+        assert_eq!(
+            vec![Utf8PathBuf::from(
+                "tests/tomls/client-tomls/selectors/by-serial.toml"
+            )],
+            effective.sources.files
+        );
+    }
+
+    #[test]
+    fn traces_rules_to_their_ruleset() {
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/rulesets").unwrap();
+        let effective = store.effective("machine-id-1234", None).unwrap();
+        let path = Utf8PathBuf::from("tests/tomls/client-tomls/rulesets");
+        assert_eq!(
+            vec![
+                path.join("rulesets/browsers.toml"),
+                path.join("machine-id-1234.toml"),
+                path.join("rulesets/dev-tools/compilers.toml"),
+                path.join("rulesets/dev-tools.toml"),
+            ],
+            effective.sources.rules
+        );
+        assert_eq!(None, effective.sources.preflight.get("include_rulesets"));
+    }
+}
@@ -0,0 +1,102 @@
+use camino::Utf8Path;
+use santa_types::RuleType;
+use serde::{Deserialize, Serialize};
+
+const PLATFORM: &str = "platform";
+const TEAM_ID_LEN: usize = 10;
+
+/// What loading client configs does with a rule whose identifier is malformed.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentifierValidation {
+    /// The client configs fail to load.
+    Reject,
+    /// The rule is served as it is, and logged as a warning. The default, so that configs that
+    /// loaded before identifiers were checked keep loading.
+    #[default]
+    Warn,
+}
+
+/// Checks that a rule identifier has the form Santa expects for its rule type. Santa never matches
+/// a malformed identifier, so a typo would otherwise only show up as a rule that does nothing.
+pub fn validate_identifier(rule_type: &RuleType, identifier: &str) -> Result<(), String> {
+    let valid = match rule_type {
+        RuleType::Binary | RuleType::Certificate => is_hex(identifier, 64),
+        RuleType::CdHash => is_hex(identifier, 40),
+        RuleType::TeamId => is_team_id(identifier),
+        RuleType::SigningId => match identifier.split_once(':') {
+            Some((prefix, id)) => (prefix == PLATFORM || is_team_id(prefix)) && !id.is_empty(),
+            None => false,
+        },
+    };
+    if valid {
+        return Ok(());
+    }
+    let expected = match rule_type {
+        RuleType::Binary | RuleType::Certificate => "64 hexadecimal characters",
+        RuleType::CdHash => "40 hexadecimal characters",
+        RuleType::TeamId => "10 letters and digits",
+        RuleType::SigningId => "TEAMID:bundle.id or platform:bundle.id",
+    };
+    Err(format!(
+        "{} identifier \"{identifier}\" must be {expected}",
+        rule_type_name(rule_type)
+    ))
+}
+
+/// Checks the identifier of every rule in `file`, describing each malformed one.
+pub(crate) fn invalid_identifiers(file: &Utf8Path, rules: &[santa_types::Rule]) -> Vec<String> {
+    rules
+        .iter()
+        .filter_map(|rule| validate_identifier(&rule.rule_type, &rule.identifier).err())
+        .map(|e| format!("invalid rule in \"{file}\", {e}"))
+        .collect()
+}
+
+/// The name Santa uses for a rule type.
+fn rule_type_name(rule_type: &RuleType) -> &'static str {
+    match rule_type {
+        RuleType::Binary => "BINARY",
+        RuleType::CdHash => "CDHASH",
+        RuleType::Certificate => "CERTIFICATE",
+        RuleType::SigningId => "SIGNINGID",
+        RuleType::TeamId => "TEAMID",
+    }
+}
+
+fn is_hex(identifier: &str, len: usize) -> bool {
+    identifier.len() == len && identifier.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_team_id(identifier: &str) -> bool {
+    identifier.len() == TEAM_ID_LEN && identifier.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_identifiers_by_rule_type() {
+        let sha256 = "2dc104631939b4bdf5d6bccab76e166e37fe5e1605340cf68dab919df58b8eda";
+        assert!(validate_identifier(&RuleType::Binary, sha256).is_ok());
+        assert!(validate_identifier(&RuleType::Certificate, sha256).is_ok());
+        assert!(validate_identifier(&RuleType::Binary, &sha256[1..]).is_err());
+        assert!(validate_identifier(&RuleType::CdHash, &sha256[..40]).is_ok());
+        assert!(validate_identifier(&RuleType::CdHash, sha256).is_err());
+        assert!(validate_identifier(&RuleType::TeamId, "EQHXZ8M8AV").is_ok());
+        assert!(validate_identifier(&RuleType::TeamId, "EQHXZ8M8A").is_err());
+        assert!(validate_identifier(&RuleType::SigningId, "EQHXZ8M8AV:com.google.Chrome").is_ok());
+        assert!(validate_identifier(&RuleType::SigningId, "platform:com.apple.curl").is_ok());
+        assert!(validate_identifier(&RuleType::SigningId, "com.google.Chrome").is_err());
+        assert!(validate_identifier(&RuleType::SigningId, "EQHXZ8M8AV:").is_err());
+    }
+
+    #[test]
+    fn describes_malformed_identifiers() {
+        assert_eq!(
+            "TEAMID identifier \"EQHXZ8M8A\" must be 10 letters and digits",
+            validate_identifier(&RuleType::TeamId, "EQHXZ8M8A").unwrap_err()
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs;
+
+use anyhow::anyhow;
+use camino::Utf8Path;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use crate::config_store::{RuleFile, same_rule, validate_identifier};
+
+/// A rule export written by `santactl rule --export`.
+#[derive(Deserialize)]
+struct RuleExport {
+    rules: Vec<ExportedRule>,
+}
+
+/// A rule as Santa exports it, which is also how moroz configs hold rules. Policies and rule types
+/// are read as strings so that every invalid rule can be reported, not just the first. Older
+/// versions of both call the identifier `sha256`.
+#[derive(Deserialize)]
+pub(crate) struct ExportedRule {
+    #[serde(alias = "sha256")]
+    pub identifier: String,
+    pub policy: String,
+    pub rule_type: String,
+    #[serde(default)]
+    pub custom_msg: Option<String>,
+    #[serde(default)]
+    pub custom_url: Option<String>,
+}
+
+/// What importing rules changed in a client config.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+pub fn read_rule_export<P: AsRef<Utf8Path>>(
+    path: P,
+) -> Result<Vec<santa_types::Rule>, anyhow::Error> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow!("could not read rule export \"{}\", {}", path, e))?;
+    parse_rule_export(&contents).map_err(|e| anyhow!("invalid rule export \"{}\", {}", path, e))
+}
+
+/// Parses the rules in a Santa rule export, checking each policy, rule type and identifier.
+pub fn parse_rule_export(contents: &str) -> Result<Vec<santa_types::Rule>, anyhow::Error> {
+    let export: RuleExport = serde_json::from_str(contents)?;
+    let mut errors: Vec<String> = vec![];
+    let mut rules = vec![];
+    for (index, exported) in export.rules.into_iter().enumerate() {
+        match to_rule(exported) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => errors.push(format!("rule {}: {}", index + 1, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!("{}", errors.join(", ")));
+    }
+    Ok(rules)
+}
+
+pub(crate) fn to_rule(exported: ExportedRule) -> Result<santa_types::Rule, String> {
+    let policy: santa_types::Policy = match exported.policy.as_str() {
+        "WHITELIST" => santa_types::Policy::Allowlist,
+        "WHITELIST_COMPILER" => santa_types::Policy::AllowlistCompiler,
+        "BLACKLIST" => santa_types::Policy::Blocklist,
+        "SILENT_BLACKLIST" => santa_types::Policy::SilentBlocklist,
+        "REMOVE" => return Err("REMOVE rules cannot be imported".to_string()),
+        policy => from_name(policy).ok_or_else(|| format!("unknown policy \"{policy}\""))?,
+    };
+    let rule_type: santa_types::RuleType = from_name(&exported.rule_type)
+        .ok_or_else(|| format!("unknown rule type \"{}\"", exported.rule_type))?;
+    validate_identifier(&rule_type, &exported.identifier)?;
+    Ok(santa_types::Rule {
+        identifier: exported.identifier,
+        policy,
+        rule_type,
+        custom_msg: exported.custom_msg.filter(|msg| !msg.is_empty()),
+        custom_url: exported.custom_url.filter(|url| !url.is_empty()),
+        creation_time: None,
+        file_bundle_binary_count: None,
+        file_bundle_hash: None,
+    })
+}
+
+/// Reads a policy or rule type by the name Santa uses for it.
+fn from_name<T: DeserializeOwned>(name: &str) -> Option<T> {
+    serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+}
+
+/// Adds `rules` to the rules in `file`. A rule that is already there, by rule type and identifier,
+/// takes the policy and messages of the imported rule and keeps its place, so importing the same
+/// export twice changes nothing.
+pub fn import_rules(
+    file: &mut RuleFile,
+    rules: &[santa_types::Rule],
+) -> Result<ImportSummary, anyhow::Error> {
+    let mut existing = file.rules()?;
+    let mut summary = ImportSummary::default();
+    for rule in rules {
+        match existing
+            .iter_mut()
+            .find(|existing| same_rule(existing, rule))
+        {
+            Some(existing)
+                if existing.policy == rule.policy
+                    && existing.custom_msg == rule.custom_msg
+                    && existing.custom_url == rule.custom_url =>
+            {
+                summary.unchanged += 1;
+            }
+            Some(existing) => {
+                existing.policy = rule.policy.clone();
+                existing.custom_msg = rule.custom_msg.clone();
+                existing.custom_url = rule.custom_url.clone();
+                summary.updated += 1;
+            }
+            None => {
+                existing.push(rule.clone());
+                summary.added += 1;
+            }
+        }
+    }
+    file.set_rules(&existing)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT_PATH: &str = "tests/santactl/export.json";
+
+    #[test]
+    fn reads_santactl_rule_exports() {
+        let rules = read_rule_export(EXPORT_PATH).unwrap();
+        assert_eq!(4, rules.len());
+        assert_eq!(santa_types::RuleType::Binary, rules[0].rule_type);
+        assert_eq!(santa_types::Policy::Blocklist, rules[0].policy);
+        assert_eq!(Some("blocklist firefox".to_string()), rules[0].custom_msg);
+        assert_eq!(santa_types::Policy::Allowlist, rules[1].policy);
+        assert_eq!(None, rules[1].custom_msg);
+        assert_eq!("EQHXZ8M8AV:com.google.Chrome", rules[3].identifier);
+    }
+
+    #[test]
+    fn reports_every_invalid_rule() {
+        let export = r#"{"rules": [
+            {"identifier": "EQHXZ8M8AV", "policy": "ALLOWLIST", "rule_type": "TEAMID"},
+            {"identifier": "EQHXZ8M8A", "policy": "ALLOWLIST", "rule_type": "TEAMID"},
+            {"identifier": "EQHXZ8M8AV", "policy": "CEL", "rule_type": "TEAMID"},
+            {"identifier": "EQHXZ8M8AV", "policy": "ALLOWLIST", "rule_type": "TEAM"}
+        ]}"#;
+        assert_eq!(
+            "rule 2: TEAMID identifier \"EQHXZ8M8A\" must be 10 letters and digits, \
+             rule 3: unknown policy \"CEL\", rule 4: unknown rule type \"TEAM\"",
+            parse_rule_export(export).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn deduplicates_imported_rules() {
+        let contents = r#"client_mode = "MONITOR"
+
+[[rules]]
+rule_type = "TEAMID"
+policy = "BLOCKLIST"
+identifier = "EQHXZ8M8AV"
+custom_msg = "block google team id"
+"#;
+        let mut file = RuleFile::create("machine-id-1234.toml", contents).unwrap();
+        let rules = read_rule_export(EXPORT_PATH).unwrap();
+
+        let summary = import_rules(&mut file, &rules).unwrap();
+        assert_eq!(
+            ImportSummary {
+                added: 3,
+                updated: 1,
+                unchanged: 0,
+            },
+            summary
+        );
+        let imported = file.rules().unwrap();
+        assert_eq!(4, imported.len());
+        assert_eq!("EQHXZ8M8AV", imported[0].identifier);
+        assert_eq!(santa_types::Policy::Allowlist, imported[0].policy);
+
+        let summary = import_rules(&mut file, &rules).unwrap();
+        assert_eq!(4, summary.unchanged);
+        assert_eq!(imported, file.rules().unwrap());
+    }
+}
@@ -1,5 +1,39 @@
 mod client_config;
-pub use client_config::ClientConfig;
+pub(crate) use client_config::is_safe_file_stem;
+pub use client_config::{ClientConfig, ConfigFormat};
+
+mod effective;
+pub use effective::{EffectiveConfig, EffectiveSources};
+
+mod identifier;
+pub(crate) use identifier::invalid_identifiers;
+pub use identifier::{IdentifierValidation, validate_identifier};
+
+mod import;
+pub(crate) use import::{ExportedRule, to_rule};
+pub use import::{ImportSummary, import_rules, parse_rule_export, read_rule_export};
+
+mod overlay;
+pub use overlay::{ClientConfigOverlay, PreflightOverrides};
+
+mod responses;
+pub use responses::{PreparedResponses, RulePage};
+
+mod rule_file;
+pub use rule_file::{RuleFile, same_rule};
+
+mod ruleset;
+pub use ruleset::RuleSet;
+pub(crate) use ruleset::{included_rules, is_ruleset_file};
+
+mod selector;
+pub use selector::{Selector, VersionRange};
 
 mod store;
-pub use store::ConfigStore;
+pub(crate) use store::find_config_files;
+pub use store::{ClientConfigMode, ConfigStore, ConfigStoreOptions, GLOBAL, GROUP, Sources};
+
+mod reload;
+#[cfg(unix)]
+pub use reload::reload_on_sighup;
+pub use reload::{SharedConfigStore, log_warnings, reload_on_change};
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+
+use crate::config_store::client_config::{machine_id_for, read_config};
+use crate::config_store::{ClientConfig, Selector};
+
+/// A client config that only holds the settings it changes. Group and machine specific files are
+/// read as overlays when client configs are merged with `global.toml`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ClientConfigOverlay {
+    #[serde(skip)]
+    pub machine_id: String,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(flatten)]
+    pub preflight: PreflightOverrides,
+    /// Rule sets whose rules come before the rules in this file. Their rules are already in
+    /// `rules` once the store is loaded.
+    #[serde(default)]
+    pub include_rulesets: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<santa_types::Rule>,
+    #[serde(default)]
+    pub selector: Option<Selector>,
+}
+
+impl ClientConfigOverlay {
+    pub fn from_file<P: AsRef<Utf8Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let mut config: Self = read_config(path)?;
+        config.machine_id = machine_id_for(path);
+
+        Ok(config)
+    }
+
+    /// Layers this overlay on top of `config`.
+    ///
+    /// Preflight settings set in the overlay replace the ones in `config`. A rule replaces the rule
+    /// in `config` with the same identifier and rule type, in place; every other rule is appended.
+    pub fn apply_to(&self, config: &mut ClientConfig) {
+        self.preflight.apply(&mut config.preflight);
+        merge_rules(&mut config.rules, &self.rules);
+    }
+}
+
+/// The preflight settings an overlay can change. Every field of `santa_types::Preflight` needs a
+/// field here and a line in `apply`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PreflightOverrides {
+    pub enable_bundles: Option<bool>,
+    pub enable_transitive_rules: Option<bool>,
+    pub batch_size: Option<u32>,
+    pub full_sync_interval: Option<u32>,
+    pub client_mode: Option<santa_types::ClientMode>,
+    pub allowed_path_regex: Option<String>,
+    pub blocked_path_regex: Option<String>,
+    pub block_usb_mount: Option<bool>,
+    pub remount_usb_mode: Option<String>,
+    pub sync_type: Option<santa_types::SyncType>,
+    pub override_file_access_action: Option<santa_types::OverrideFileAccessAction>,
+}
+
+impl PreflightOverrides {
+    pub fn apply(&self, preflight: &mut santa_types::Preflight) {
+        if let Some(enable_bundles) = self.enable_bundles {
+            preflight.enable_bundles = enable_bundles;
+        }
+        if let Some(enable_transitive_rules) = self.enable_transitive_rules {
+            preflight.enable_transitive_rules = enable_transitive_rules;
+        }
+        if let Some(batch_size) = self.batch_size {
+            preflight.batch_size = Some(batch_size);
+        }
+        if let Some(full_sync_interval) = self.full_sync_interval {
+            preflight.full_sync_interval = full_sync_interval;
+        }
+        if let Some(client_mode) = &self.client_mode {
+            preflight.client_mode = client_mode.clone();
+        }
+        if let Some(allowed_path_regex) = &self.allowed_path_regex {
+            preflight.allowed_path_regex = Some(allowed_path_regex.clone());
+        }
+        if let Some(blocked_path_regex) = &self.blocked_path_regex {
+            preflight.blocked_path_regex = Some(blocked_path_regex.clone());
+        }
+        if let Some(block_usb_mount) = self.block_usb_mount {
+            preflight.block_usb_mount = block_usb_mount;
+        }
+        if let Some(remount_usb_mode) = &self.remount_usb_mode {
+            preflight.remount_usb_mode = Some(remount_usb_mode.clone());
+        }
+        if let Some(sync_type) = &self.sync_type {
+            preflight.sync_type = sync_type.clone();
+        }
+        if let Some(override_file_access_action) = &self.override_file_access_action {
+            preflight.override_file_access_action = override_file_access_action.clone();
+        }
+    }
+}
+
+fn merge_rules(rules: &mut Vec<santa_types::Rule>, overrides: &[santa_types::Rule]) {
+    let mut positions: HashMap<(santa_types::RuleType, String), usize> = rules
+        .iter()
+        .enumerate()
+        .map(|(index, rule)| ((rule.rule_type.clone(), rule.identifier.clone()), index))
+        .collect();
+    for rule in overrides {
+        let key = (rule.rule_type.clone(), rule.identifier.clone());
+        match positions.get(&key) {
+            Some(&index) => rules[index] = rule.clone(),
+            None => {
+                positions.insert(key, rules.len());
+                rules.push(rule.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MERGE_PATH: &str = "tests/tomls/client-tomls/merge";
+
+    fn field_names(value: serde_json::Value) -> Vec<String> {
+        let mut names = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn overrides_every_preflight_setting() {
+        let schema = serde_json::to_value(schemars::schema_for!(santa_types::Preflight)).unwrap();
+        let overrides = serde_json::to_value(PreflightOverrides::default()).unwrap();
+        assert_eq!(
+            field_names(schema["properties"].clone()),
+            field_names(overrides)
+        );
+
+        let global = ClientConfig::from_file(format!("{MERGE_PATH}/global.toml")).unwrap();
+        let changed = santa_types::Preflight {
+            enable_bundles: !global.preflight.enable_bundles,
+            enable_transitive_rules: !global.preflight.enable_transitive_rules,
+            batch_size: Some(global.preflight.batch_size.unwrap_or_default() + 1),
+            full_sync_interval: global.preflight.full_sync_interval + 1,
+            client_mode: match global.preflight.client_mode {
+                santa_types::ClientMode::Lockdown => santa_types::ClientMode::Monitor,
+                santa_types::ClientMode::Monitor => santa_types::ClientMode::Lockdown,
+            },
+            allowed_path_regex: Some("^/changed/allowed".to_string()),
+            blocked_path_regex: Some("^/changed/blocked".to_string()),
+            block_usb_mount: !global.preflight.block_usb_mount,
+            remount_usb_mode: Some("changed".to_string()),
+            sync_type: match global.preflight.sync_type {
+                santa_types::SyncType::CleanAll => santa_types::SyncType::Normal,
+                _ => santa_types::SyncType::CleanAll,
+            },
+            override_file_access_action: match global.preflight.override_file_access_action {
+                santa_types::OverrideFileAccessAction::Disable => {
+                    santa_types::OverrideFileAccessAction::None
+                }
+                _ => santa_types::OverrideFileAccessAction::Disable,
+            },
+        };
+        let overrides: PreflightOverrides =
+            serde_json::from_value(serde_json::to_value(&changed).unwrap()).unwrap();
+        let mut preflight = global.preflight.clone();
+        overrides.apply(&mut preflight);
+        assert_eq!(changed, preflight);
+    }
+
+    #[test]
+    fn parses_a_partial_config_file() {
+        let overlay =
+            ClientConfigOverlay::from_file(format!("{MERGE_PATH}/machine-id-1234.toml")).unwrap();
+        assert_eq!("machine-id-1234".to_string(), overlay.machine_id);
+        assert_eq!(
+            Some(santa_types::ClientMode::Lockdown),
+            overlay.preflight.client_mode
+        );
+        assert!(overlay.preflight.full_sync_interval.is_none());
+        assert_eq!(2, overlay.rules.len());
+    }
+
+    #[test]
+    fn overrides_preflight_settings_and_rules() {
+        let global = ClientConfig::from_file(format!("{MERGE_PATH}/global.toml")).unwrap();
+        let overlay =
+            ClientConfigOverlay::from_file(format!("{MERGE_PATH}/machine-id-1234.toml")).unwrap();
+
+        let mut config = global.clone();
+        overlay.apply_to(&mut config);
+
+        assert_eq!(
+            santa_types::ClientMode::Lockdown,
+            config.preflight.client_mode
+        );
+        assert_eq!(
+            global.preflight.full_sync_interval,
+            config.preflight.full_sync_interval
+        );
+        assert_eq!(3, config.rules.len());
+        assert_eq!(global.rules[0], config.rules[0]);
+        assert_eq!(global.rules[1].identifier, config.rules[1].identifier);
+        assert_eq!(santa_types::Policy::Allowlist, config.rules[1].policy);
+        assert_eq!(overlay.rules[1], config.rules[2]);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use slog::{Logger, error, info, warn};
+
+use crate::config_store::{ClientConfig, ConfigStore};
+use crate::metrics::METRICS;
+
+/// A handle to the active `ConfigStore` that can be swapped while the server is running.
+///
+/// Readers take a snapshot of the store, so a request keeps using the same client configs even if
+/// a reload happens while it is in flight.
+#[derive(Clone, Debug)]
+pub struct SharedConfigStore {
+    current: Arc<RwLock<Arc<ConfigStore>>>,
+    last_reload_error: Arc<RwLock<Option<String>>>,
+}
+
+impl SharedConfigStore {
+    pub fn new(store: ConfigStore) -> Self {
+        METRICS.client_configs.set(store.cache.len() as i64);
+        SharedConfigStore {
+            current: Arc::new(RwLock::new(Arc::new(store))),
+            last_reload_error: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn load(&self) -> Arc<ConfigStore> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn config_for(&self, machine_id: &str) -> Arc<ClientConfig> {
+        self.load().config_for(machine_id)
+    }
+
+    /// Loads the client configs from the store's path again and swaps them in. The current store
+    /// stays in use if any file fails to load.
+    pub fn reload(&self) -> Result<(), anyhow::Error> {
+        let current = self.load();
+        let store = ConfigStore::from_path_with_options(&current.path, current.options.clone())?;
+        METRICS.client_configs.set(store.cache.len() as i64);
+        *self.current.write().unwrap() = Arc::new(store);
+        *self.last_reload_error.write().unwrap() = None;
+        Ok(())
+    }
+
+    /// Why the last reload of changed client config files failed, until a reload succeeds. The
+    /// store is still serving the client configs it loaded before.
+    pub fn last_reload_error(&self) -> Option<String> {
+        self.last_reload_error.read().unwrap().clone()
+    }
+
+    pub(crate) fn reload_and_log(&self, log: &Logger, trigger: &str) {
+        match self.reload() {
+            Ok(()) => {
+                info!(log, "reloaded client configs"; "trigger" => trigger);
+                log_warnings(&self.load(), log);
+            }
+            Err(e) => {
+                error!(
+                    log,
+                    "failed to reload client configs, keeping the current configs";
+                    "trigger" => trigger,
+                    "error" => format!("{e:#}"),
+                );
+                *self.last_reload_error.write().unwrap() = Some(format!("{e:#}"));
+            }
+        }
+    }
+}
+
+/// Logs what the store loaded but would otherwise go unnoticed: files under its path that are not
+/// client configs, which are easy to miss when a config file has the wrong extension, and rules
+/// with a malformed identifier, which Santa never matches.
+pub fn log_warnings(store: &ConfigStore, log: &Logger) {
+    for file in &store.skipped {
+        warn!(
+            log,
+            "skipped file with an unknown client config extension";
+            "path" => file.as_str(),
+        );
+    }
+    for invalid in &store.invalid_identifiers {
+        warn!(
+            log,
+            "serving a rule with a malformed identifier";
+            "error" => invalid.as_str(),
+        );
+    }
+}
+
+/// Reloads the store every time the process receives SIGHUP.
+#[cfg(unix)]
+pub fn reload_on_sighup(store: SharedConfigStore, log: Logger) -> Result<(), std::io::Error> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            store.reload_and_log(&log, "SIGHUP");
+        }
+    });
+    Ok(())
+}
+
+/// Polls the store's path and reloads the store whenever a file is added, removed, or modified.
+pub fn reload_on_change(store: SharedConfigStore, log: Logger, interval: Duration) {
+    tokio::spawn(async move {
+        let path = store.load().path.clone();
+        let mut last_seen = fingerprint(&path).ok();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let current = match fingerprint(&path) {
+                Ok(current) => Some(current),
+                Err(e) => {
+                    error!(log, "failed to scan client config path"; "error" => e.to_string());
+                    continue;
+                }
+            };
+            if current != last_seen {
+                last_seen = current;
+                store.reload_and_log(&log, "client config path changed");
+            }
+        }
+    });
+}
+
+type Fingerprint = BTreeMap<Utf8PathBuf, (Option<SystemTime>, u64)>;
+
+fn fingerprint<P: AsRef<Utf8Path>>(path: P) -> Result<Fingerprint, std::io::Error> {
+    let mut queue: VecDeque<Utf8PathBuf> = VecDeque::new();
+    queue.push_back(path.as_ref().to_path_buf());
+    let mut files = BTreeMap::new();
+
+    while let Some(dir_path) = queue.pop_front() {
+        for entry in fs::read_dir(dir_path)? {
+            let entry = entry?;
+            let entry_path = Utf8PathBuf::from_path_buf(entry.path()).expect("valid UTF-8 path");
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                queue.push_back(entry_path);
+            } else {
+                files.insert(entry_path, (metadata.modified().ok(), metadata.len()));
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy_good_configs(test_name: &str) -> Utf8PathBuf {
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("chimney-{}-{}", test_name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for entry in fs::read_dir("tests/tomls/client-tomls/good").unwrap() {
+            let entry = entry.unwrap();
+            fs::copy(entry.path(), dir.join(entry.file_name().to_str().unwrap())).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn reload_swaps_in_new_configs() {
+        let dir = copy_good_configs("reload_swaps_in_new_configs");
+        let store = SharedConfigStore::new(ConfigStore::from_path(&dir).unwrap());
+        let snapshot = store.load();
+        fs::remove_file(dir.join("machine-id-5678.toml")).unwrap();
+
+        store.reload().unwrap();
+
+        assert_eq!(3, snapshot.cache.len());
+        assert_eq!(2, store.load().cache.len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reload_keeps_current_configs_on_error() {
+        let dir = copy_good_configs("reload_keeps_current_configs_on_error");
+        let store = SharedConfigStore::new(ConfigStore::from_path(&dir).unwrap());
+        fs::write(dir.join("machine-id-9999.toml"), "client_mode = ").unwrap();
+
+        let result = store.reload();
+
+        assert!(result.is_err());
+        assert_eq!(3, store.load().cache.len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_failed_reload_until_a_reload_succeeds() {
+        let dir = copy_good_configs("reports_failed_reload_until_a_reload_succeeds");
+        let store = SharedConfigStore::new(ConfigStore::from_path(&dir).unwrap());
+        let log = Logger::root(slog::Discard, slog::o!());
+        fs::write(dir.join("machine-id-9999.toml"), "client_mode = ").unwrap();
+
+        store.reload_and_log(&log, "test");
+        let error = store.last_reload_error().unwrap();
+        assert!(error.contains("machine-id-9999.toml"), "{error}");
+
+        fs::remove_file(dir.join("machine-id-9999.toml")).unwrap();
+        store.reload_and_log(&log, "test");
+        assert_eq!(None, store.last_reload_error());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fingerprint_changes_when_a_file_changes() {
+        let dir = copy_good_configs("fingerprint_changes_when_a_file_changes");
+        let before = fingerprint(&dir).unwrap();
+        fs::write(dir.join("machine-id-9999.toml"), "").unwrap();
+
+        let after = fingerprint(&dir).unwrap();
+
+        assert_eq!(3, before.len());
+        assert_ne!(before, after);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
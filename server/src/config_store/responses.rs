@@ -0,0 +1,200 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, OnceLock};
+
+use anyhow::anyhow;
+
+use crate::config_store::ClientConfig;
+use crate::response::PreparedBody;
+
+/// The sync responses for one client config. Each is serialized the first time it is requested
+/// and shared by every request after that, until the store is reloaded.
+#[derive(Debug)]
+pub struct PreparedResponses {
+    config: Arc<ClientConfig>,
+    preflight: OnceLock<Arc<PreparedBody>>,
+    rule_pages: OnceLock<RulePages>,
+}
+
+#[derive(Debug)]
+struct RulePages {
+    page_size: usize,
+    /// Identifies the rules the pages were cut from. Every cursor carries it, so a cursor handed
+    /// out before a reload changed the rules is not used to page through the new ones.
+    version: String,
+    pages: Vec<RulePage>,
+}
+
+/// One page of a `ruledownload` response.
+#[derive(Clone, Debug)]
+pub struct RulePage {
+    pub body: Arc<PreparedBody>,
+    /// The number of rules in the page.
+    pub rules: usize,
+}
+
+impl PreparedResponses {
+    pub fn new(config: Arc<ClientConfig>) -> Self {
+        PreparedResponses {
+            config,
+            preflight: OnceLock::new(),
+            rule_pages: OnceLock::new(),
+        }
+    }
+
+    pub fn preflight(&self) -> Arc<PreparedBody> {
+        self.preflight
+            .get_or_init(|| Arc::new(PreparedBody::new(&self.config.preflight)))
+            .clone()
+    }
+
+    /// The page of rules the cursor Santa sent back points at, or the first page without one.
+    /// Fails if the cursor is malformed or was handed out for rules that have changed since.
+    /// Pages are cached for the first `page_size` asked for, which is the same on every request,
+    /// and only at the offsets chimney hands out as cursors. Any other page is built for the
+    /// request.
+    pub fn rule_page(
+        &self,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<RulePage, anyhow::Error> {
+        let rule_pages = self.rule_pages.get_or_init(|| {
+            let version = rules_version(&self.config.rules);
+            RulePages {
+                page_size,
+                pages: (0..self.config.rules.len().max(1))
+                    .step_by(page_size.max(1))
+                    .map(|offset| rule_page(&self.config.rules, &version, offset, page_size))
+                    .collect(),
+                version,
+            }
+        });
+        let offset = match cursor {
+            Some(cursor) => parse_cursor(cursor, &rule_pages.version)?,
+            None => 0,
+        };
+        let step = page_size.max(1);
+        if rule_pages.page_size == page_size
+            && offset.is_multiple_of(step)
+            && let Some(page) = rule_pages.pages.get(offset / step)
+        {
+            return Ok(page.clone());
+        }
+        Ok(rule_page(
+            &self.config.rules,
+            &rule_pages.version,
+            offset,
+            page_size,
+        ))
+    }
+}
+
+/// The cursor is the version of the rules and the offset of the first rule in the next page. It is
+/// opaque to Santa, which sends it back unchanged until the server stops returning one.
+fn rule_page(
+    rules: &[santa_types::Rule],
+    version: &str,
+    offset: usize,
+    page_size: usize,
+) -> RulePage {
+    let next = offset.saturating_add(page_size);
+    let cursor = (next < rules.len()).then(|| format!("{version}-{next}"));
+    let rules = rules
+        .iter()
+        .skip(offset)
+        .take(page_size)
+        .cloned()
+        .collect::<Vec<_>>();
+    RulePage {
+        rules: rules.len(),
+        body: Arc::new(PreparedBody::new(&santa_types::Rules { rules, cursor })),
+    }
+}
+
+fn parse_cursor(cursor: &str, version: &str) -> Result<usize, anyhow::Error> {
+    let (cursor_version, offset) = cursor
+        .split_once('-')
+        .and_then(|(cursor_version, offset)| Some((cursor_version, offset.parse().ok()?)))
+        .ok_or_else(|| anyhow!("invalid cursor \"{}\"", cursor))?;
+    if cursor_version != version {
+        return Err(anyhow!(
+            "cursor \"{}\" is for rules that have changed since, restart the rule download",
+            cursor
+        ));
+    }
+    Ok(offset)
+}
+
+/// A hash of the rules, which stays the same across reloads and restarts that leave them as they
+/// are.
+fn rules_version(rules: &[santa_types::Rule]) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_vec(rules)
+        .expect("rules serialize to JSON")
+        .hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn responses() -> PreparedResponses {
+        let config =
+            ClientConfig::from_file("tests/tomls/client-tomls/good/machine-id-1234.toml").unwrap();
+        PreparedResponses::new(Arc::new(config))
+    }
+
+    fn cursor(responses: &PreparedResponses, offset: usize) -> String {
+        format!("{}-{offset}", rules_version(&responses.config.rules))
+    }
+
+    #[test]
+    fn shares_prepared_responses() {
+        let responses = responses();
+        assert!(Arc::ptr_eq(&responses.preflight(), &responses.preflight()));
+        let first = responses.rule_page(None, 3).unwrap();
+        assert_eq!(3, first.rules);
+        assert!(Arc::ptr_eq(
+            &first.body,
+            &responses.rule_page(None, 3).unwrap().body
+        ));
+        let last = responses.rule_page(Some(&cursor(&responses, 6)), 3);
+        assert_eq!(2, last.unwrap().rules);
+    }
+
+    #[test]
+    fn builds_pages_outside_the_cache() {
+        let responses = responses();
+        let page = |offset| {
+            responses
+                .rule_page(Some(&cursor(&responses, offset)), 3)
+                .unwrap()
+        };
+        let cached = page(3);
+        let unaligned = page(4);
+        assert_eq!(3, unaligned.rules);
+        assert!(!Arc::ptr_eq(&cached.body, &unaligned.body));
+        assert_eq!(0, page(100).rules);
+        assert_eq!(5, responses.rule_page(None, 5).unwrap().rules);
+    }
+
+    #[test]
+    fn rejects_cursors_for_other_rules() {
+        let responses = responses();
+        let config = ClientConfig::from_file("tests/tomls/client-tomls/good/global.toml").unwrap();
+        let other = PreparedResponses::new(Arc::new(config));
+        let err = responses
+            .rule_page(Some(&cursor(&other, 3)), 3)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("is for rules that have changed since")
+        );
+        for cursor in ["3", "abc-", "not-a-cursor"] {
+            let err = responses.rule_page(Some(cursor), 3).unwrap_err();
+            assert_eq!(format!("invalid cursor \"{cursor}\""), err.to_string());
+        }
+        let same = PreparedResponses::new(responses.config.clone());
+        assert!(same.rule_page(Some(&cursor(&responses, 3)), 3).is_ok());
+    }
+}
@@ -0,0 +1,258 @@
+use std::fs;
+use std::io;
+
+use anyhow::anyhow;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+use toml_edit::{ArrayOfTables, DocumentMut, Item};
+
+use crate::config_store::ConfigFormat;
+
+#[derive(Deserialize)]
+struct RulesOnly {
+    #[serde(default)]
+    rules: Vec<santa_types::Rule>,
+}
+
+/// A client config file opened for editing its rules. In TOML files everything outside of the
+/// rules, including comments, is written back unchanged. JSON and YAML files are written back
+/// with their keys in the same order, but YAML comments and formatting are not kept.
+#[derive(Debug)]
+pub struct RuleFile {
+    document: Document,
+    path: Utf8PathBuf,
+}
+
+#[derive(Debug)]
+enum Document {
+    Json(serde_json::Map<String, serde_json::Value>),
+    Toml(DocumentMut),
+    Yaml(serde_norway::Mapping),
+}
+
+impl RuleFile {
+    pub fn open<P: AsRef<Utf8Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        Self::parse(path, &contents)
+    }
+
+    /// Starts a new file at `path` from `contents`, without touching the file system. The format
+    /// is chosen by the extension of `path`, and empty `contents` start an empty config.
+    pub fn create<P: AsRef<Utf8Path>>(path: P, contents: &str) -> Result<Self, anyhow::Error> {
+        Self::parse(path.as_ref(), contents)
+    }
+
+    fn parse(path: &Utf8Path, contents: &str) -> Result<Self, anyhow::Error> {
+        let format = ConfigFormat::from_path(path)
+            .ok_or_else(|| anyhow!("unknown client config format \"{}\"", path))?;
+        let empty = contents.trim().is_empty();
+        let document = match format {
+            ConfigFormat::Json if empty => Ok(Document::Json(Default::default())),
+            ConfigFormat::Json => serde_json::from_str(contents)
+                .map(Document::Json)
+                .map_err(anyhow::Error::from),
+            ConfigFormat::Toml => contents
+                .parse()
+                .map(Document::Toml)
+                .map_err(anyhow::Error::from),
+            ConfigFormat::Yaml if empty => Ok(Document::Yaml(Default::default())),
+            ConfigFormat::Yaml => serde_norway::from_str(contents)
+                .map(Document::Yaml)
+                .map_err(anyhow::Error::from),
+        }
+        .map_err(|e| anyhow!("could not parse {} \"{}\", {}", format.name(), path, e))?;
+        Ok(RuleFile {
+            document,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    pub fn rules(&self) -> Result<Vec<santa_types::Rule>, anyhow::Error> {
+        let rules: Result<RulesOnly, anyhow::Error> = match &self.document {
+            Document::Json(object) => {
+                serde_json::from_value(object.clone().into()).map_err(anyhow::Error::from)
+            }
+            Document::Toml(document) => {
+                toml::from_str(&document.to_string()).map_err(anyhow::Error::from)
+            }
+            Document::Yaml(mapping) => {
+                serde_norway::from_value(mapping.clone().into()).map_err(anyhow::Error::from)
+            }
+        };
+        rules.map(|rules| rules.rules).map_err(|e| {
+            anyhow!(
+                "could not parse {} \"{}\", {}",
+                self.format().name(),
+                self.path,
+                e
+            )
+        })
+    }
+
+    pub fn set_rules(&mut self, rules: &[santa_types::Rule]) -> Result<(), anyhow::Error> {
+        match &mut self.document {
+            Document::Json(object) => {
+                object.insert("rules".to_string(), serde_json::to_value(rules)?);
+            }
+            Document::Toml(document) => {
+                let mut tables = ArrayOfTables::new();
+                for rule in rules {
+                    let rule = toml_edit::ser::to_document(rule)?;
+                    tables.push(rule.as_table().clone());
+                }
+                document["rules"] = Item::ArrayOfTables(tables);
+            }
+            Document::Yaml(mapping) => {
+                mapping.insert("rules".into(), serde_norway::to_value(rules)?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn contents(&self) -> String {
+        match &self.document {
+            Document::Json(object) => {
+                let mut contents =
+                    serde_json::to_string_pretty(object).expect("JSON objects serialize");
+                contents.push('\n');
+                contents
+            }
+            Document::Toml(document) => document.to_string(),
+            Document::Yaml(mapping) => {
+                serde_norway::to_string(mapping).expect("YAML mappings serialize")
+            }
+        }
+    }
+
+    fn format(&self) -> ConfigFormat {
+        match self.document {
+            Document::Json(_) => ConfigFormat::Json,
+            Document::Toml(_) => ConfigFormat::Toml,
+            Document::Yaml(_) => ConfigFormat::Yaml,
+        }
+    }
+
+    /// Writes to a temporary file first so that readers never see a partially written file.
+    pub fn save(&self) -> Result<(), io::Error> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file_name = self.path.file_name().unwrap_or_default();
+        let tmp_path = self.path.with_file_name(format!(".{file_name}.tmp"));
+        fs::write(&tmp_path, self.contents())?;
+        fs::rename(tmp_path, &self.path)
+    }
+}
+
+/// Rules are identified by their rule type and identifier.
+pub fn same_rule(left: &santa_types::Rule, right: &santa_types::Rule) -> bool {
+    left.rule_type == right.rule_type && left.identifier == right.identifier
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_rules() {
+        let file = RuleFile::open("tests/tomls/client-tomls/good/machine-id-1234.toml").unwrap();
+        assert_eq!(8, file.rules().unwrap().len());
+    }
+
+    #[test]
+    fn replaces_rules_and_keeps_everything_else() {
+        let contents = r#"# managed by the help desk
+client_mode = "MONITOR" # comment
+
+[[rules]]
+rule_type = "TEAMID"
+policy = "ALLOWLIST"
+identifier = "EQHXZ8M8AV"
+"#;
+        let mut file = RuleFile::create("machine-id-1234.toml", contents).unwrap();
+        let mut rules = file.rules().unwrap();
+        rules[0].policy = santa_types::Policy::Blocklist;
+        rules.push(santa_types::Rule {
+            identifier: "platform:com.apple.BluetoothFileExchange".to_string(),
+            policy: santa_types::Policy::Blocklist,
+            rule_type: santa_types::RuleType::SigningId,
+            custom_msg: Some("block bluetooth file exchange.app".to_string()),
+            custom_url: None,
+            creation_time: None,
+            file_bundle_binary_count: None,
+            file_bundle_hash: None,
+        });
+
+        file.set_rules(&rules).unwrap();
+
+        let contents = file.contents();
+        assert!(
+            contents
+                .starts_with("# managed by the help desk\nclient_mode = \"MONITOR\" # comment\n")
+        );
+        assert_eq!(rules, file.rules().unwrap());
+    }
+
+    #[test]
+    fn errs_on_invalid_toml() {
+        let result = RuleFile::create("bad.toml", "client_mode = ");
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert!(err_msg.contains("could not parse TOML"));
+    }
+
+    #[test]
+    fn replaces_rules_in_json_and_yaml() {
+        for path in [
+            "tests/tomls/client-tomls/formats/machine-id-1234.json",
+            "tests/tomls/client-tomls/formats/machine-id-5678.yaml",
+        ] {
+            let mut file = RuleFile::open(path).unwrap();
+            let mut rules = file.rules().unwrap();
+            assert_eq!(2, rules.len());
+            rules.remove(0);
+            file.set_rules(&rules).unwrap();
+
+            let reopened = RuleFile::create(path, &file.contents()).unwrap();
+            assert_eq!(rules, reopened.rules().unwrap());
+            let config: crate::ClientConfig = match ConfigFormat::from_path(path.into()) {
+                Some(ConfigFormat::Json) => serde_json::from_str(&file.contents()).unwrap(),
+                _ => serde_norway::from_str(&file.contents()).unwrap(),
+            };
+            assert_eq!(Some(100), config.preflight.batch_size);
+        }
+    }
+
+    #[test]
+    fn keeps_the_order_of_json_keys() {
+        let contents = r#"{"client_mode": "MONITOR", "batch_size": 100}"#;
+        let mut file = RuleFile::create("machine-id-1234.json", contents).unwrap();
+        file.set_rules(&[]).unwrap();
+        assert_eq!(
+            "{\n  \"client_mode\": \"MONITOR\",\n  \"batch_size\": 100,\n  \"rules\": []\n}\n",
+            file.contents()
+        );
+    }
+
+    #[test]
+    fn creates_empty_json_and_yaml_files() {
+        for path in ["machine-id-1234.json", "machine-id-1234.yaml"] {
+            let file = RuleFile::create(path, "").unwrap();
+            assert!(file.rules().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn errs_on_unknown_formats() {
+        let result = RuleFile::create("machine-id-1234.ini", "");
+        assert_eq!(
+            "unknown client config format \"machine-id-1234.ini\"",
+            result.unwrap_err().to_string()
+        );
+    }
+}
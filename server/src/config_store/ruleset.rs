@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::anyhow;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+
+use crate::config_store::client_config::read_config;
+use crate::config_store::store::check_one_format;
+use crate::config_store::{ConfigFormat, find_config_files};
+
+/// The subdirectory of `client_config_path` that holds rule sets.
+pub const RULESETS: &str = "rulesets";
+type RuleSetName = String;
+
+/// A named set of rules that client configs pull in with `include_rulesets`. A rule set can
+/// include other rule sets, whose rules come before its own.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    #[serde(default)]
+    pub include_rulesets: Vec<RuleSetName>,
+    #[serde(default)]
+    pub rules: Vec<santa_types::Rule>,
+}
+
+impl RuleSet {
+    pub fn from_file<P: AsRef<Utf8Path>>(path: P) -> Result<Self, anyhow::Error> {
+        read_config(path.as_ref())
+    }
+}
+
+/// The rule sets under `root`, by name, and the file each was read from.
+#[derive(Debug, Default)]
+pub(crate) struct LoadedRuleSets {
+    pub rulesets: BTreeMap<RuleSetName, RuleSet>,
+    pub sources: BTreeMap<RuleSetName, Utf8PathBuf>,
+}
+
+/// Whether `file` is in the rule set directory under `root`, rather than a client config.
+pub(crate) fn is_ruleset_file(root: &Utf8Path, file: &Utf8Path) -> bool {
+    file.starts_with(root.join(RULESETS))
+}
+
+/// Reads every rule set under `root`. A rule set is named after its path relative to the rule set
+/// directory, without the extension, e.g. `browsers` or `dev-tools/compilers`. Errs on rule sets
+/// that include unknown rule sets or include each other.
+pub(crate) fn load_rulesets(root: &Utf8Path) -> Result<LoadedRuleSets, anyhow::Error> {
+    let dir = root.join(RULESETS);
+    let mut loaded = LoadedRuleSets::default();
+    if !dir.is_dir() {
+        return Ok(loaded);
+    }
+    let files: Vec<Utf8PathBuf> = find_config_files(&dir)?
+        .into_iter()
+        .filter(|file| ConfigFormat::from_path(file).is_some())
+        .collect();
+    check_one_format(&files)?;
+    let mut errors: Vec<String> = vec![];
+    for file in files {
+        match RuleSet::from_file(&file) {
+            Ok(ruleset) => {
+                let name = ruleset_name(&dir, &file);
+                loaded.sources.insert(name.clone(), file);
+                loaded.rulesets.insert(name, ruleset);
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    if errors.is_empty() {
+        for (name, ruleset) in &loaded.rulesets {
+            let includer = format!("rule set \"{name}\"");
+            if let Err(e) = included_rules(&loaded.rulesets, &includer, &ruleset.include_rulesets) {
+                errors.push(e);
+            }
+        }
+    }
+    if !errors.is_empty() {
+        Err(anyhow!("{}", errors.join(", ")))
+    } else {
+        Ok(loaded)
+    }
+}
+
+fn ruleset_name(dir: &Utf8Path, file: &Utf8Path) -> RuleSetName {
+    file.strip_prefix(dir)
+        .expect("rule sets are under the rule set directory")
+        .with_extension("")
+        .components()
+        .map(|component| component.as_str())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The rules of the rule sets `names`, in order, with the name of the rule set each came from.
+/// The rules of an included rule set come before the rules of the rule set that includes it. A
+/// rule replaces an earlier rule with the same identifier and rule type, in place.
+///
+/// `includer` describes what includes `names`, for errors about unknown rule sets.
+pub(crate) fn included_rules<'a>(
+    rulesets: &'a BTreeMap<RuleSetName, RuleSet>,
+    includer: &str,
+    names: &'a [RuleSetName],
+) -> Result<Vec<(&'a str, &'a santa_types::Rule)>, String> {
+    Ok(Included::new(rulesets, includer, names)?.rules)
+}
+
+/// The rules in `rulesets` that `names` include, followed by `rules`, merged the way
+/// `included_rules` merges them.
+pub(crate) fn with_included_rules(
+    rulesets: &BTreeMap<RuleSetName, RuleSet>,
+    includer: &str,
+    names: &[RuleSetName],
+    rules: &[santa_types::Rule],
+) -> Result<Vec<santa_types::Rule>, String> {
+    let mut included = Included::new(rulesets, includer, names)?;
+    for rule in rules {
+        included.push("", rule);
+    }
+    Ok(included
+        .rules
+        .into_iter()
+        .map(|(_, rule)| rule.clone())
+        .collect())
+}
+
+#[derive(Default)]
+struct Included<'a> {
+    positions: HashMap<(santa_types::RuleType, &'a str), usize>,
+    rules: Vec<(&'a str, &'a santa_types::Rule)>,
+}
+
+impl<'a> Included<'a> {
+    fn new(
+        rulesets: &'a BTreeMap<RuleSetName, RuleSet>,
+        includer: &str,
+        names: &'a [RuleSetName],
+    ) -> Result<Self, String> {
+        let mut included = Included::default();
+        for name in names {
+            included.include(rulesets, includer, name, &mut vec![])?;
+        }
+        Ok(included)
+    }
+
+    fn include(
+        &mut self,
+        rulesets: &'a BTreeMap<RuleSetName, RuleSet>,
+        includer: &str,
+        name: &'a str,
+        stack: &mut Vec<&'a str>,
+    ) -> Result<(), String> {
+        if let Some(start) = stack.iter().position(|included| *included == name) {
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(name);
+            return Err(format!(
+                "rule sets include each other: {}",
+                cycle.join(" -> ")
+            ));
+        }
+        let Some(ruleset) = rulesets.get(name) else {
+            let includer = match stack.last() {
+                Some(parent) => format!("rule set \"{parent}\""),
+                None => includer.to_string(),
+            };
+            return Err(format!("{includer} includes unknown rule set \"{name}\""));
+        };
+        stack.push(name);
+        for included in &ruleset.include_rulesets {
+            self.include(rulesets, includer, included, stack)?;
+        }
+        stack.pop();
+        for rule in &ruleset.rules {
+            self.push(name, rule);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, name: &'a str, rule: &'a santa_types::Rule) {
+        let key = (rule.rule_type.clone(), rule.identifier.as_str());
+        match self.positions.get(&key) {
+            Some(&index) => self.rules[index] = (name, rule),
+            None => {
+                self.positions.insert(key, self.rules.len());
+                self.rules.push((name, rule));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULESETS_PATH: &str = "tests/tomls/client-tomls/rulesets";
+
+    #[test]
+    fn loads_rulesets_by_name() {
+        let loaded = load_rulesets(Utf8Path::new(RULESETS_PATH)).unwrap();
+        assert_eq!(
+            vec!["browsers", "dev-tools", "dev-tools/compilers"],
+            loaded.rulesets.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Utf8PathBuf::from(format!("{RULESETS_PATH}/rulesets/dev-tools/compilers.toml")),
+            loaded.sources["dev-tools/compilers"]
+        );
+    }
+
+    #[test]
+    fn includes_nested_rulesets_in_order() {
+        let loaded = load_rulesets(Utf8Path::new(RULESETS_PATH)).unwrap();
+        let names = ["browsers".to_string(), "dev-tools".to_string()];
+        let rules = included_rules(&loaded.rulesets, "test", &names).unwrap();
+        let sources = rules.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(
+            vec!["browsers", "browsers", "dev-tools/compilers", "dev-tools"],
+            sources
+        );
+    }
+
+    #[test]
+    fn errs_on_unknown_and_cyclic_rulesets() {
+        let mut rulesets = BTreeMap::new();
+        let including = |names: &[&str]| RuleSet {
+            include_rulesets: names.iter().map(ToString::to_string).collect(),
+            rules: vec![],
+        };
+        rulesets.insert("a".to_string(), including(&["b"]));
+        rulesets.insert("b".to_string(), including(&["c"]));
+        rulesets.insert("c".to_string(), including(&["a"]));
+        rulesets.insert("d".to_string(), including(&["missing"]));
+
+        assert_eq!(
+            "rule sets include each other: a -> b -> c -> a",
+            included_rules(&rulesets, "test", &["a".to_string()]).unwrap_err()
+        );
+        assert_eq!(
+            "rule set \"d\" includes unknown rule set \"missing\"",
+            included_rules(&rulesets, "test", &["d".to_string()]).unwrap_err()
+        );
+        assert_eq!(
+            "client config \"x.toml\" includes unknown rule set \"e\"",
+            included_rules(&rulesets, "client config \"x.toml\"", &["e".to_string()]).unwrap_err()
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::cmp::Ordering;
+
+use serde::Deserialize;
+
+/// Matches a client config to a machine using the attributes Santa sends in its preflight
+/// request. Every criterion that is set must match. A criterion with several patterns matches if
+/// any of them does.
+///
+/// Patterns are matched case-insensitively and support `*` and `?` wildcards.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Selector {
+    #[serde(default)]
+    pub serial_num: Vec<String>,
+    #[serde(default)]
+    pub hostname: Vec<String>,
+    #[serde(default)]
+    pub primary_user: Vec<String>,
+    #[serde(default)]
+    pub model_identifier: Vec<String>,
+    pub os_version: Option<VersionRange>,
+    pub santa_version: Option<VersionRange>,
+}
+
+impl Selector {
+    pub fn is_empty(&self) -> bool {
+        self.serial_num.is_empty()
+            && self.hostname.is_empty()
+            && self.primary_user.is_empty()
+            && self.model_identifier.is_empty()
+            && self.os_version.is_none()
+            && self.santa_version.is_none()
+    }
+
+    pub fn matches(&self, options: &santa_types::PreflightOptions) -> bool {
+        matches_any(&self.serial_num, Some(&options.serial_num))
+            && matches_any(&self.hostname, Some(&options.hostname))
+            && matches_any(&self.primary_user, options.primary_user.as_ref())
+            && matches_any(&self.model_identifier, options.model_identifier.as_ref())
+            && self
+                .os_version
+                .as_ref()
+                .is_none_or(|range| range.contains(&options.os_version))
+            && self
+                .santa_version
+                .as_ref()
+                .is_none_or(|range| range.contains(&options.santa_version))
+    }
+}
+
+fn matches_any(patterns: &[String], value: Option<&String>) -> bool {
+    if patterns.is_empty() {
+        return true;
+    }
+    match value {
+        Some(value) => patterns.iter().any(|pattern| glob_match(pattern, value)),
+        None => false,
+    }
+}
+
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let value: Vec<char> = value.to_lowercase().chars().collect();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    v = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// A comma separated list of version comparisons, e.g. `">=14.0, <15"`. Versions are compared
+/// component by component, and missing components count as 0.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct VersionRange {
+    comparisons: Vec<(Comparator, Vec<u64>)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparator {
+    Equal,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl VersionRange {
+    pub fn contains(&self, version: &str) -> bool {
+        let Some(version) = parse_version(version) else {
+            return false;
+        };
+        self.comparisons.iter().all(|(comparator, bound)| {
+            let ordering = compare_versions(&version, bound);
+            match comparator {
+                Comparator::Equal => ordering == Ordering::Equal,
+                Comparator::Greater => ordering == Ordering::Greater,
+                Comparator::GreaterOrEqual => ordering != Ordering::Less,
+                Comparator::Less => ordering == Ordering::Less,
+                Comparator::LessOrEqual => ordering != Ordering::Greater,
+            }
+        })
+    }
+}
+
+impl TryFrom<String> for VersionRange {
+    type Error = String;
+
+    fn try_from(range: String) -> Result<Self, Self::Error> {
+        let mut comparisons = vec![];
+        for comparison in range.split(',').map(str::trim) {
+            let (comparator, version) = if let Some(version) = comparison.strip_prefix(">=") {
+                (Comparator::GreaterOrEqual, version)
+            } else if let Some(version) = comparison.strip_prefix("<=") {
+                (Comparator::LessOrEqual, version)
+            } else if let Some(version) = comparison.strip_prefix('>') {
+                (Comparator::Greater, version)
+            } else if let Some(version) = comparison.strip_prefix('<') {
+                (Comparator::Less, version)
+            } else if let Some(version) = comparison.strip_prefix('=') {
+                (Comparator::Equal, version)
+            } else {
+                (Comparator::Equal, comparison)
+            };
+            let version = parse_version(version.trim())
+                .ok_or_else(|| format!("invalid version range \"{range}\""))?;
+            comparisons.push((comparator, version));
+        }
+        Ok(VersionRange { comparisons })
+    }
+}
+
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .split('.')
+        .map(|component| component.parse::<u64>().ok())
+        .collect()
+}
+
+fn compare_versions(left: &[u64], right: &[u64]) -> Ordering {
+    let len = left.len().max(right.len());
+    (0..len)
+        .map(|i| {
+            let l = left.get(i).copied().unwrap_or(0);
+            let r = right.get(i).copied().unwrap_or(0);
+            l.cmp(&r)
+        })
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::preflight_options;
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_match("eng-*", "eng-laptop-42"));
+        assert!(glob_match("ENG-*.corp.*", "eng-laptop-42.corp.example.com"));
+        assert!(glob_match("eng-laptop-4?", "eng-laptop-42"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("eng-*", "ops-laptop-42"));
+        assert!(!glob_match("eng-laptop-4?", "eng-laptop-420"));
+    }
+
+    #[test]
+    fn version_range_compares_components() {
+        let range = VersionRange::try_from(">=14.0, <15".to_string()).unwrap();
+        assert!(range.contains("14"));
+        assert!(range.contains("14.4.1"));
+        assert!(!range.contains("13.6.7"));
+        assert!(!range.contains("15.0"));
+        assert!(!range.contains("not-a-version"));
+
+        let range = VersionRange::try_from("2024.1".to_string()).unwrap();
+        assert!(range.contains("2024.1.0"));
+        assert!(!range.contains("2024.2"));
+    }
+
+    #[test]
+    fn errs_on_invalid_version_range() {
+        let result = VersionRange::try_from(">=fourteen".to_string());
+        assert_eq!(
+            Err("invalid version range \">=fourteen\"".to_string()),
+            result
+        );
+    }
+
+    #[test]
+    fn matches_when_every_criterion_matches() {
+        let selector = Selector {
+            hostname: vec!["ops-*".to_string(), "eng-*".to_string()],
+            primary_user: vec!["alice".to_string()],
+            os_version: Some(VersionRange::try_from(">=14".to_string()).unwrap()),
+            ..Default::default()
+        };
+        assert!(selector.matches(&preflight_options(
+            "C02SERIAL",
+            "eng-laptop-42.corp.example.com"
+        )));
+    }
+
+    #[test]
+    fn does_not_match_when_a_criterion_does_not_match() {
+        let selector = Selector {
+            hostname: vec!["eng-*".to_string()],
+            model_identifier: vec!["MacBookPro*".to_string()],
+            ..Default::default()
+        };
+        assert!(!selector.matches(&preflight_options(
+            "C02SERIAL",
+            "eng-laptop-42.corp.example.com"
+        )));
+    }
+}
@@ -1,27 +1,90 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fs;
+use std::sync::Arc;
 
 use anyhow::anyhow;
 use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
 
-use crate::config_store::ClientConfig;
+use crate::config_store::ruleset::{
+    LoadedRuleSets, is_ruleset_file, load_rulesets, with_included_rules,
+};
+use crate::config_store::{
+    ClientConfig, ClientConfigOverlay, ConfigFormat, IdentifierValidation, PreparedResponses,
+    RuleSet, invalid_identifiers,
+};
 
 pub const GLOBAL: &str = "global";
+pub const GROUP: &str = "group";
+type GroupName = String;
 type MachineId = String;
+type RuleSetName = String;
+
+/// How machine specific client configs relate to `global.toml`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientConfigMode {
+    /// A machine specific file is a complete client config that is used instead of `global.toml`.
+    #[default]
+    Replace,
+    /// Group and machine specific files only hold the preflight settings they override and any
+    /// additional rules. The effective config is `global.toml`, then each group the machine
+    /// belongs to, then the machine specific file, layered in that order.
+    Merge,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConfigStoreOptions {
+    pub mode: ClientConfigMode,
+    pub identifier_validation: IdentifierValidation,
+}
 
 #[derive(Debug)]
 pub struct ConfigStore {
-    pub cache: HashMap<MachineId, ClientConfig>,
+    pub cache: HashMap<MachineId, Arc<ClientConfig>>,
+    pub groups: BTreeMap<GroupName, ClientConfigOverlay>,
+    /// Rules with a malformed identifier that are served anyway, because `options` only warns
+    /// about them.
+    pub invalid_identifiers: Vec<String>,
+    pub options: ConfigStoreOptions,
     pub path: Utf8PathBuf,
+    /// The sync responses for each config in `cache`.
+    pub responses: HashMap<MachineId, PreparedResponses>,
+    /// The rule sets under `rulesets/`, which have already been included in the configs in
+    /// `cache`.
+    pub rulesets: BTreeMap<RuleSetName, RuleSet>,
+    /// Configs that can be matched by preflight attributes, in the order they are tried.
+    pub selectable: Vec<MachineId>,
+    /// Files under `path` that were not read, because their extension is not a client config
+    /// format.
+    pub skipped: Vec<Utf8PathBuf>,
+    /// The file each global, machine specific, and group config, and each rule set, was read from.
+    pub sources: Sources,
 }
 
-fn find_config_files<P: AsRef<Utf8Path>>(
+#[derive(Debug, Default)]
+pub struct Sources {
+    pub configs: HashMap<MachineId, Utf8PathBuf>,
+    pub groups: BTreeMap<GroupName, Utf8PathBuf>,
+    pub rulesets: BTreeMap<RuleSetName, Utf8PathBuf>,
+}
+
+#[derive(Default)]
+struct Loaded {
+    cache: HashMap<MachineId, ClientConfig>,
+    groups: BTreeMap<GroupName, ClientConfigOverlay>,
+    /// Malformed rule identifiers, and the files they are in.
+    invalid: Vec<String>,
+    rulesets: BTreeMap<RuleSetName, RuleSet>,
+    sources: Sources,
+}
+
+pub(crate) fn find_config_files<P: AsRef<Utf8Path>>(
     path: P,
-    config_map: &mut HashMap<MachineId, ClientConfig>,
-) -> Result<(), anyhow::Error> {
+) -> Result<Vec<Utf8PathBuf>, anyhow::Error> {
     let mut queue: VecDeque<Utf8PathBuf> = VecDeque::new();
     queue.push_back(path.as_ref().to_path_buf());
-    let mut errors: Vec<String> = vec![];
+    let mut files = vec![];
 
     while let Some(dir_path) = queue.pop_front() {
         for entry in fs::read_dir(dir_path)? {
@@ -30,15 +93,64 @@ fn find_config_files<P: AsRef<Utf8Path>>(
             if entry_path.is_dir() {
                 queue.push_back(entry_path);
             } else {
-                match ClientConfig::from_file(entry_path) {
-                    Ok(config) => {
-                        config_map
-                            .entry(config.machine_id.clone())
-                            .or_insert(config);
-                    }
-                    Err(e) => errors.push(e.to_string()),
+                files.push(entry_path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Errs if the same config is in more than one format, e.g. `global.toml` and `global.json`,
+/// because it would be unclear which one is used.
+pub(super) fn check_one_format(files: &[Utf8PathBuf]) -> Result<(), anyhow::Error> {
+    let mut by_stem: BTreeMap<Utf8PathBuf, Vec<&Utf8PathBuf>> = BTreeMap::new();
+    for file in files {
+        by_stem
+            .entry(file.with_extension(""))
+            .or_default()
+            .push(file);
+    }
+    let errors: Vec<String> = by_stem
+        .into_values()
+        .filter(|files| files.len() > 1)
+        .map(|mut files| {
+            files.sort();
+            let files: Vec<String> = files.iter().map(|file| format!("\"{file}\"")).collect();
+            format!("{} are the same config in two formats", files.join(" and "))
+        })
+        .collect();
+    if !errors.is_empty() {
+        Err(anyhow!("{}", errors.join(", ")))
+    } else {
+        Ok(())
+    }
+}
+
+fn load_configs(files: Vec<Utf8PathBuf>, loaded: &mut Loaded) -> Result<(), anyhow::Error> {
+    let mut errors: Vec<String> = vec![];
+    for file in files {
+        if file.file_stem() == Some(GROUP) {
+            errors.push(format!(
+                "group config \"{file}\" requires client_config_mode = \"merge\""
+            ));
+            continue;
+        }
+        match ClientConfig::from_file(&file) {
+            Ok(mut config) => {
+                let included = &config.include_rulesets;
+                if let Err(e) = prepare_rules(loaded, &file, included, &mut config.rules) {
+                    errors.push(e);
+                    continue;
+                }
+                if !loaded.cache.contains_key(&config.machine_id) {
+                    loaded
+                        .sources
+                        .configs
+                        .insert(config.machine_id.clone(), file);
+                    loaded.cache.insert(config.machine_id.clone(), config);
                 }
             }
+            Err(e) => errors.push(e.to_string()),
         }
     }
     if !errors.is_empty() {
@@ -48,40 +160,293 @@ fn find_config_files<P: AsRef<Utf8Path>>(
     }
 }
 
+/// Checks the identifiers of a client config's own rules, then puts the rules of the rule sets it
+/// includes before them.
+fn prepare_rules(
+    loaded: &mut Loaded,
+    file: &Utf8Path,
+    included: &[RuleSetName],
+    rules: &mut Vec<santa_types::Rule>,
+) -> Result<(), String> {
+    loaded.invalid.extend(invalid_identifiers(file, rules));
+    let includer = format!("client config \"{file}\"");
+    *rules = with_included_rules(&loaded.rulesets, &includer, included, rules)?;
+    Ok(())
+}
+
+fn read_overlay(loaded: &mut Loaded, file: &Utf8Path) -> Result<ClientConfigOverlay, String> {
+    let mut overlay = ClientConfigOverlay::from_file(file).map_err(|e| e.to_string())?;
+    let included = &overlay.include_rulesets;
+    prepare_rules(loaded, file, included, &mut overlay.rules)?;
+    Ok(overlay)
+}
+
+fn merge_configs(
+    root: &Utf8Path,
+    files: Vec<Utf8PathBuf>,
+    loaded: &mut Loaded,
+) -> Result<(), anyhow::Error> {
+    let mut errors: Vec<String> = vec![];
+    let mut global: Option<(Utf8PathBuf, ClientConfig)> = None;
+    let mut overlays: Vec<(Utf8PathBuf, ClientConfigOverlay)> = vec![];
+    for file in files {
+        if file.file_stem() == Some(GLOBAL) {
+            match ClientConfig::from_file(&file) {
+                Ok(mut config) => {
+                    let included = &config.include_rulesets;
+                    match prepare_rules(loaded, &file, included, &mut config.rules) {
+                        Ok(()) => {
+                            global.get_or_insert((file, config));
+                        }
+                        Err(e) => errors.push(e),
+                    }
+                }
+                Err(e) => errors.push(e.to_string()),
+            }
+        } else if file.file_stem() == Some(GROUP) {
+            let name = group_name(root, &file);
+            match read_overlay(loaded, &file) {
+                Ok(_) if name.is_empty() => errors.push(format!(
+                    "group config \"{file}\" must be in a subdirectory named after the group"
+                )),
+                Ok(overlay) if !overlay.groups.is_empty() => errors.push(format!(
+                    "group config \"{file}\" cannot belong to other groups"
+                )),
+                Ok(overlay) if overlay.selector.is_some() => {
+                    errors.push(format!("group config \"{file}\" cannot have a selector"))
+                }
+                Ok(overlay) => {
+                    if !loaded.groups.contains_key(&name) {
+                        loaded.sources.groups.insert(name.clone(), file);
+                        loaded.groups.insert(name, overlay);
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+        } else {
+            match read_overlay(loaded, &file) {
+                Ok(overlay) => overlays.push((file, overlay)),
+                Err(e) => errors.push(e),
+            }
+        }
+    }
+    if let Some((_, global)) = &global {
+        for (file, overlay) in overlays {
+            if loaded.cache.contains_key(&overlay.machine_id) {
+                continue;
+            }
+            let mut config = global.clone();
+            for group in memberships(root, &file, &overlay, &loaded.groups) {
+                match loaded.groups.get(&group) {
+                    Some(group_overlay) => group_overlay.apply_to(&mut config),
+                    None => errors.push(format!(
+                        "machine config \"{file}\" references unknown group \"{group}\""
+                    )),
+                }
+            }
+            overlay.apply_to(&mut config);
+            config.machine_id = overlay.machine_id;
+            config.selector = overlay.selector;
+            loaded
+                .sources
+                .configs
+                .insert(config.machine_id.clone(), file);
+            loaded.cache.insert(config.machine_id.clone(), config);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!("{}", errors.join(", ")));
+    }
+    if let Some((file, global)) = global {
+        loaded.sources.configs.insert(GLOBAL.to_string(), file);
+        loaded.cache.insert(GLOBAL.to_string(), global);
+    }
+    Ok(())
+}
+
+fn selectable_configs(
+    config_map: &HashMap<MachineId, ClientConfig>,
+) -> Result<Vec<MachineId>, anyhow::Error> {
+    let mut errors: Vec<String> = vec![];
+    let mut selectable: Vec<MachineId> = vec![];
+    for (machine_id, config) in config_map {
+        match &config.selector {
+            Some(_) if machine_id == GLOBAL => {
+                errors.push(format!("{GLOBAL} config cannot have a selector"))
+            }
+            Some(selector) if selector.is_empty() => errors.push(format!(
+                "selector for config \"{machine_id}\" must have at least one criterion"
+            )),
+            Some(_) => selectable.push(machine_id.clone()),
+            None => {}
+        }
+    }
+    if !errors.is_empty() {
+        errors.sort();
+        return Err(anyhow!("{}", errors.join(", ")));
+    }
+    selectable.sort();
+    Ok(selectable)
+}
+
+/// A group is named after its directory, relative to the client config path.
+fn group_name(root: &Utf8Path, file: &Utf8Path) -> GroupName {
+    let dir = file.parent().unwrap_or(root);
+    dir.strip_prefix(root)
+        .unwrap_or(dir)
+        .components()
+        .map(|component| component.as_str())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The groups a machine belongs to, in the order they are layered: the groups of the directories
+/// the file is in, outermost first, followed by the groups listed in the file.
+pub(super) fn memberships(
+    root: &Utf8Path,
+    file: &Utf8Path,
+    overlay: &ClientConfigOverlay,
+    groups: &BTreeMap<GroupName, ClientConfigOverlay>,
+) -> Vec<GroupName> {
+    let dir_name = group_name(root, file);
+    let mut memberships: Vec<GroupName> = vec![];
+    let mut prefix = String::new();
+    for part in dir_name.split('/').filter(|part| !part.is_empty()) {
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+        prefix.push_str(part);
+        if groups.contains_key(&prefix) {
+            memberships.push(prefix.clone());
+        }
+    }
+    for group in &overlay.groups {
+        if !memberships.contains(group) {
+            memberships.push(group.clone());
+        }
+    }
+    memberships
+}
+
 impl ConfigStore {
     pub fn from_path<P: AsRef<Utf8Path>>(path: P) -> Result<Self, anyhow::Error> {
+        Self::from_path_with_options(path, ConfigStoreOptions::default())
+    }
+
+    pub fn from_path_with_options<P: AsRef<Utf8Path>>(
+        path: P,
+        options: ConfigStoreOptions,
+    ) -> Result<Self, anyhow::Error> {
         let path_ref = path.as_ref();
         if !path_ref.is_dir() {
             return Err(anyhow!("path {:?} is not a directory", path_ref));
         }
-        let mut cache = HashMap::new();
-        find_config_files(path_ref, &mut cache)?;
+        let LoadedRuleSets { rulesets, sources } = load_rulesets(path_ref)?;
+        let mut loaded = Loaded {
+            invalid: rulesets
+                .iter()
+                .flat_map(|(name, ruleset)| invalid_identifiers(&sources[name], &ruleset.rules))
+                .collect(),
+            rulesets,
+            sources: Sources {
+                rulesets: sources,
+                ..Sources::default()
+            },
+            ..Loaded::default()
+        };
+        let (mut files, mut skipped): (Vec<_>, Vec<_>) = find_config_files(path_ref)?
+            .into_iter()
+            .partition(|file| ConfigFormat::from_path(file).is_some());
+        files.retain(|file| !is_ruleset_file(path_ref, file));
+        skipped.sort();
+        check_one_format(&files)?;
+        match options.mode {
+            ClientConfigMode::Replace => load_configs(files, &mut loaded)?,
+            ClientConfigMode::Merge => merge_configs(path_ref, files, &mut loaded)?,
+        }
+        let Loaded {
+            cache,
+            groups,
+            mut invalid,
+            rulesets,
+            sources,
+        } = loaded;
+        invalid.sort();
+        if options.identifier_validation == IdentifierValidation::Reject && !invalid.is_empty() {
+            return Err(anyhow!("{}", invalid.join(", ")));
+        }
         if !cache.contains_key(GLOBAL) {
             return Err(anyhow!(
-                "path {:?} does not contain {}.toml",
+                "path {:?} does not contain {}.toml, .json or .yaml",
                 path_ref,
                 GLOBAL
             ));
         }
+        let selectable = selectable_configs(&cache)?;
+        let cache: HashMap<MachineId, Arc<ClientConfig>> = cache
+            .into_iter()
+            .map(|(machine_id, config)| (machine_id, Arc::new(config)))
+            .collect();
+        let responses = cache
+            .iter()
+            .map(|(machine_id, config)| {
+                (machine_id.clone(), PreparedResponses::new(config.clone()))
+            })
+            .collect();
 
         Ok(ConfigStore {
             cache,
+            groups,
+            invalid_identifiers: invalid,
+            options,
             path: path_ref.to_path_buf(),
+            responses,
+            rulesets,
+            selectable,
+            skipped,
+            sources,
         })
     }
 
-    pub fn config_for(&self, machine_id: &str) -> ClientConfig {
+    /// Picks the config for a machine: its machine specific config if there is one, otherwise the
+    /// first config, by name, whose selector matches the preflight request, otherwise global.
+    pub fn select(&self, machine_id: &str, options: &santa_types::PreflightOptions) -> MachineId {
+        if self.cache.contains_key(machine_id) {
+            return machine_id.to_string();
+        }
+        self.selectable
+            .iter()
+            .find(|key| {
+                self.cache[*key]
+                    .selector
+                    .as_ref()
+                    .is_some_and(|selector| selector.matches(options))
+            })
+            .cloned()
+            .unwrap_or_else(|| GLOBAL.to_string())
+    }
+
+    pub fn config_for(&self, machine_id: &str) -> Arc<ClientConfig> {
         self.cache
             .get(machine_id)
             .or_else(|| self.cache.get(GLOBAL))
             .cloned()
             .unwrap()
     }
+
+    /// The sync responses for the config `config_for` returns.
+    pub fn responses_for(&self, machine_id: &str) -> &PreparedResponses {
+        self.responses
+            .get(machine_id)
+            .or_else(|| self.responses.get(GLOBAL))
+            .unwrap()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::preflight_options;
 
     #[test]
     fn errs_if_path_is_non_dir() {
@@ -124,4 +489,239 @@ mod tests {
         assert_eq!(GLOBAL, config.machine_id);
         assert_eq!(0, config.rules.len());
     }
+
+    #[test]
+    fn reads_config_files_by_extension_and_skips_the_rest() {
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/formats").unwrap();
+        assert_eq!(3, store.cache.len());
+        assert_eq!(2, store.config_for("machine-id-1234").rules.len());
+        assert_eq!(2, store.config_for("machine-id-5678").rules.len());
+        assert_eq!(
+            vec![Utf8PathBuf::from(
+                "tests/tomls/client-tomls/formats/README.md"
+            )],
+            store.skipped
+        );
+    }
+
+    #[test]
+    fn includes_rulesets_before_a_configs_own_rules() {
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/rulesets").unwrap();
+        assert_eq!(2, store.cache.len());
+        assert_eq!(3, store.rulesets.len());
+        assert_eq!(2, store.config_for(GLOBAL).rules.len());
+
+        let rules = &store.config_for("machine-id-1234").rules;
+        let identifiers = rules
+            .iter()
+            .map(|rule| &rule.identifier[..10])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec!["2dc1046319", "EQHXZ8M8AV", "60d79d1763", "e88617cfd6"],
+            identifiers
+        );
+        assert_eq!(santa_types::Policy::Blocklist, rules[1].policy);
+    }
+
+    #[test]
+    fn includes_rulesets_in_merged_configs() {
+        let options = ConfigStoreOptions {
+            mode: ClientConfigMode::Merge,
+            ..ConfigStoreOptions::default()
+        };
+        let store =
+            ConfigStore::from_path_with_options("tests/tomls/client-tomls/rulesets", options)
+                .unwrap();
+        assert_eq!(4, store.config_for("machine-id-1234").rules.len());
+    }
+
+    #[test]
+    fn errs_on_unknown_rulesets() {
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/bad-rulesets");
+        assert!(
+            store
+                .unwrap_err()
+                .to_string()
+                .contains("includes unknown rule set \"missing\"")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_identifiers() {
+        let options = ConfigStoreOptions {
+            identifier_validation: IdentifierValidation::Reject,
+            ..ConfigStoreOptions::default()
+        };
+        let store = ConfigStore::from_path_with_options(
+            "tests/tomls/client-tomls/bad-identifiers",
+            options,
+        );
+        assert_eq!(
+            "invalid rule in \"tests/tomls/client-tomls/bad-identifiers/machine-id-1234.toml\", \
+             TEAMID identifier \"EQHXZ8M8A\" must be 10 letters and digits, \
+             invalid rule in \"tests/tomls/client-tomls/bad-identifiers/rulesets/compilers.toml\", \
+             BINARY identifier \"60d79d1763fefb56716e4a36284300523eb4335c3726fb9070fa83074b02279\" \
+             must be 64 hexadecimal characters",
+            store.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn warns_about_malformed_identifiers() {
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/bad-identifiers").unwrap();
+        assert_eq!(2, store.invalid_identifiers.len());
+        assert_eq!(2, store.config_for("machine-id-1234").rules.len());
+
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/good").unwrap();
+        assert!(store.invalid_identifiers.is_empty());
+    }
+
+    #[test]
+    fn errs_if_a_config_is_in_more_than_one_format() {
+        let files = [
+            "global.toml",
+            "machine-id-1234.json",
+            "machine-id-1234.toml",
+        ]
+        .map(Utf8PathBuf::from);
+        assert_eq!(
+            "\"machine-id-1234.json\" and \"machine-id-1234.toml\" are the same config in two formats",
+            check_one_format(&files).unwrap_err().to_string()
+        );
+        assert!(check_one_format(&files[..2]).is_ok());
+    }
+
+    #[test]
+    fn errs_if_merge_path_is_read_as_complete_configs() {
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/merge");
+        assert!(store.is_err());
+    }
+
+    #[test]
+    fn merges_machine_config_onto_global_config() {
+        let options = ConfigStoreOptions {
+            mode: ClientConfigMode::Merge,
+            ..ConfigStoreOptions::default()
+        };
+        let store =
+            ConfigStore::from_path_with_options("tests/tomls/client-tomls/merge", options).unwrap();
+        let global = store.config_for(GLOBAL);
+        let config = store.config_for("machine-id-1234");
+        assert_eq!(2, store.cache.len());
+        assert_eq!("machine-id-1234", config.machine_id);
+        assert_eq!(
+            santa_types::ClientMode::Lockdown,
+            config.preflight.client_mode
+        );
+        assert_eq!(
+            santa_types::ClientMode::Monitor,
+            global.preflight.client_mode
+        );
+        assert_eq!(3, config.rules.len());
+        assert_eq!(2, global.rules.len());
+    }
+
+    #[test]
+    fn merge_mode_falls_back_to_global_config() {
+        let options = ConfigStoreOptions {
+            mode: ClientConfigMode::Merge,
+            ..ConfigStoreOptions::default()
+        };
+        let store =
+            ConfigStore::from_path_with_options("tests/tomls/client-tomls/merge", options).unwrap();
+        let config = store.config_for("nonexistent-machine-id");
+        assert_eq!(GLOBAL, config.machine_id);
+        assert_eq!(2, config.rules.len());
+    }
+
+    #[test]
+    fn layers_group_configs_between_global_and_machine_configs() {
+        let options = ConfigStoreOptions {
+            mode: ClientConfigMode::Merge,
+            ..ConfigStoreOptions::default()
+        };
+        let store = ConfigStore::from_path_with_options("tests/tomls/client-tomls/groups", options)
+            .unwrap();
+        assert_eq!(
+            vec!["engineering".to_string(), "security".to_string()],
+            store.groups.keys().cloned().collect::<Vec<_>>()
+        );
+
+        let config = store.config_for("machine-id-1234");
+        assert_eq!(300, config.preflight.full_sync_interval);
+        assert_eq!(
+            santa_types::ClientMode::Monitor,
+            config.preflight.client_mode
+        );
+        assert!(config.preflight.block_usb_mount);
+        assert_eq!(
+            vec![
+                "2dc104631939b4bdf5d6bccab76e166e37fe5e1605340cf68dab919df58b8eda",
+                "EQHXZ8M8AV",
+                "EQHXZ8M8AV:com.google.Chrome",
+            ],
+            config
+                .rules
+                .iter()
+                .map(|rule| rule.identifier.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(santa_types::Policy::Allowlist, config.rules[1].policy);
+
+        let config = store.config_for("machine-id-5678");
+        assert_eq!(600, config.preflight.full_sync_interval);
+        assert_eq!(
+            santa_types::ClientMode::Lockdown,
+            config.preflight.client_mode
+        );
+        assert!(config.preflight.block_usb_mount);
+    }
+
+    #[test]
+    fn errs_if_machine_config_references_unknown_group() {
+        let options = ConfigStoreOptions {
+            mode: ClientConfigMode::Merge,
+            ..ConfigStoreOptions::default()
+        };
+        let result =
+            ConfigStore::from_path_with_options("tests/tomls/client-tomls/bad-groups", options);
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert!(err_msg.contains("references unknown group \"nonexistent\""));
+    }
+
+    #[test]
+    fn errs_on_group_configs_in_replace_mode() {
+        let result = ConfigStore::from_path("tests/tomls/client-tomls/groups");
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert!(err_msg.contains("requires client_config_mode = \"merge\""));
+    }
+
+    #[test]
+    fn selects_config_by_preflight_attributes() {
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/selectors").unwrap();
+        assert_eq!(vec!["by-serial", "eng-laptops"], store.selectable);
+        assert_eq!(
+            "by-serial",
+            store.select("unknown", &preflight_options("C02SERIAL", "eng-1"))
+        );
+        assert_eq!(
+            "eng-laptops",
+            store.select("unknown", &preflight_options("OTHER", "eng-1"))
+        );
+        assert_eq!(
+            GLOBAL,
+            store.select("unknown", &preflight_options("OTHER", "ops-1"))
+        );
+    }
+
+    #[test]
+    fn prefers_machine_specific_config_over_selectors() {
+        let store = ConfigStore::from_path("tests/tomls/client-tomls/selectors").unwrap();
+        assert_eq!(
+            "machine-id-1234",
+            store.select("machine-id-1234", &preflight_options("C02SERIAL", "eng-1"))
+        );
+    }
 }
@@ -1,8 +1,11 @@
-use dropshot::{HttpError, HttpResponseOk, Path, RequestContext};
-use slog::{info, o};
+use dropshot::{Body, ClientErrorStatusCode, HttpError, HttpResponseOk, Path, RequestContext};
+use slog::{info, o, warn};
 
 use crate::api::{MachineId, SantaSyncServerApi};
-use crate::body::CompressedTypedBody;
+use crate::body::{CompressedTypedBody, EventUploadBody};
+use crate::health::Health;
+use crate::metrics::{METRICS, observe};
+use crate::response::CompressedResponse;
 use crate::{Context, SantaSyncServerApiImpl};
 use santa_types::Empty;
 
@@ -12,44 +15,202 @@ impl SantaSyncServerApi for SantaSyncServerApiImpl {
     async fn preflight_post(
         rqctx: RequestContext<Self::Context>,
         path_params: Path<MachineId>,
-        _body_params: CompressedTypedBody<santa_types::PreflightOptions>,
-    ) -> Result<HttpResponseOk<santa_types::Preflight>, HttpError> {
-        let machine_id = path_params.into_inner().machine_id;
-        let client_config = rqctx.context().store.config_for(&machine_id);
-        Ok(HttpResponseOk(client_config.preflight))
+        body_params: CompressedTypedBody<santa_types::PreflightOptions>,
+    ) -> Result<CompressedResponse<santa_types::Preflight>, HttpError> {
+        observe("preflight", async {
+            let machine_id = path_params.into_inner().machine_id;
+            authorize(&rqctx, &machine_id)?;
+            let preflight_options = body_params.read(&rqctx).await?;
+            let context = rqctx.context();
+            let store = context.store.load();
+            let config_key = store.select(&machine_id, &preflight_options);
+            let preflight = store.responses_for(&config_key).preflight();
+            if let Some(inventory) = &context.inventory
+                && let Err(e) = inventory
+                    .record_preflight(&machine_id, preflight_options, &config_key)
+                    .await
+            {
+                warn!(rqctx.log, "failed to record preflight"; "error" => e.to_string());
+            }
+            context.sessions.start(&machine_id, config_key);
+            Ok(CompressedResponse::prepared(&rqctx, preflight))
+        })
+        .await
     }
 
     async fn eventupload_post(
         rqctx: RequestContext<Self::Context>,
         path_params: Path<MachineId>,
-        body_params: CompressedTypedBody<santa_types::EventUploadOptions>,
+        body_params: EventUploadBody,
     ) -> Result<HttpResponseOk<Empty>, HttpError> {
-        if let Some(log) = &rqctx.context().event_log {
+        observe("eventupload", async {
             let machine_id = path_params.into_inner().machine_id;
-            let log = log.new(o!("machine_id" => machine_id));
-            let event_upload_options = body_params.into_inner();
-            for event in event_upload_options.events.iter() {
-                info!(log, ""; &event);
-            }
-        }
-        Ok(HttpResponseOk(Empty {}))
+            authorize(&rqctx, &machine_id)?;
+            let log = rqctx
+                .context()
+                .event_log
+                .as_ref()
+                .map(|log| log.new(o!("machine_id" => machine_id)));
+            // Santa retries a rejected upload as a whole, so nothing is logged unless every
+            // event in it can be parsed.
+            body_params
+                .for_each_event(&rqctx, |event| {
+                    METRICS
+                        .events_uploaded
+                        .with_label_values(&[decision_label(&event.decision)])
+                        .inc();
+                    if let Some(log) = &log {
+                        info!(log, ""; &event);
+                    }
+                })
+                .await?;
+            Ok(HttpResponseOk(Empty {}))
+        })
+        .await
     }
 
     async fn ruledownload_post(
         rqctx: RequestContext<Self::Context>,
         path_params: Path<MachineId>,
-    ) -> Result<HttpResponseOk<santa_types::Rules>, HttpError> {
-        let machine_id = path_params.into_inner().machine_id;
-        let client_config = rqctx.context().store.config_for(&machine_id);
-        let rules = client_config.rules;
-        Ok(HttpResponseOk(santa_types::Rules { rules }))
+        body_params: CompressedTypedBody<santa_types::RuleDownloadOptions>,
+    ) -> Result<CompressedResponse<santa_types::Rules>, HttpError> {
+        observe("ruledownload", async {
+            let machine_id = path_params.into_inner().machine_id;
+            authorize(&rqctx, &machine_id)?;
+            let cursor = body_params.read(&rqctx).await?.cursor;
+            let context = rqctx.context();
+            let config_key = context.sessions.config_key(&machine_id);
+            let page = context
+                .store
+                .load()
+                .responses_for(config_key.as_deref().unwrap_or(&machine_id))
+                .rule_page(cursor.as_deref(), context.rule_download_page_size)
+                .map_err(|e| HttpError::for_bad_request(None, e.to_string()))?;
+            METRICS.rules_served.observe(page.rules as f64);
+            Ok(CompressedResponse::prepared(&rqctx, page.body))
+        })
+        .await
     }
 
     async fn postflight_post(
-        _rqctx: RequestContext<Self::Context>,
-        _path_params: Path<MachineId>,
-        _body_params: CompressedTypedBody<santa_types::PostflightOptions>,
+        rqctx: RequestContext<Self::Context>,
+        path_params: Path<MachineId>,
+        body_params: CompressedTypedBody<santa_types::PostflightOptions>,
     ) -> Result<HttpResponseOk<Empty>, HttpError> {
-        Ok(HttpResponseOk(Empty {}))
+        observe("postflight", async {
+            let machine_id = path_params.into_inner().machine_id;
+            authorize(&rqctx, &machine_id)?;
+            let postflight = body_params.read(&rqctx).await?;
+            let context = rqctx.context();
+            if let Some(inventory) = &context.inventory
+                && let Err(e) = inventory.record_postflight(&machine_id, postflight).await
+            {
+                warn!(rqctx.log, "failed to record postflight"; "error" => e.to_string());
+            }
+            context.sessions.finish(&machine_id);
+            Ok(HttpResponseOk(Empty {}))
+        })
+        .await
+    }
+
+    async fn metrics_get(
+        rqctx: RequestContext<Self::Context>,
+    ) -> Result<http::Response<Body>, HttpError> {
+        if !rqctx.context().metrics_enabled {
+            return Err(HttpError::for_client_error(
+                None,
+                ClientErrorStatusCode::NOT_FOUND,
+                "metrics are not enabled".to_string(),
+            ));
+        }
+        http::Response::builder()
+            .header(http::header::CONTENT_TYPE, prometheus::TEXT_FORMAT)
+            .body(Body::from(METRICS.encode()))
+            .map_err(|e| HttpError::for_internal_error(e.to_string()))
+    }
+
+    async fn healthz_get(
+        _rqctx: RequestContext<Self::Context>,
+    ) -> Result<HttpResponseOk<Health>, HttpError> {
+        Ok(HttpResponseOk(Health {
+            status: "ok".to_string(),
+        }))
+    }
+
+    async fn readyz_get(
+        rqctx: RequestContext<Self::Context>,
+    ) -> Result<http::Response<Body>, HttpError> {
+        let context = rqctx.context();
+        let report = context.readiness.check(&context.store);
+        let status = if report.ready {
+            http::StatusCode::OK
+        } else {
+            http::StatusCode::SERVICE_UNAVAILABLE
+        };
+        let body = serde_json::to_string(&report)
+            .map_err(|e| HttpError::for_internal_error(e.to_string()))?;
+        http::Response::builder()
+            .status(status)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .map_err(|e| HttpError::for_internal_error(e.to_string()))
+    }
+}
+
+/// The decision as Santa sends it, e.g. `BLOCK_BINARY`.
+fn decision_label(decision: &santa_types::Decision) -> String {
+    serde_json::to_value(decision)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Rejects the request unless it carries an accepted sync token, or sync auth is disabled, and,
+/// with client certificates, unless it arrived with a certificate issued for `machine_id`.
+/// Handlers call this before reading the request body, so unauthorized clients cannot make the
+/// server receive or decompress a body.
+fn authorize(rqctx: &RequestContext<Context>, machine_id: &str) -> Result<(), HttpError> {
+    let context = rqctx.context();
+    let identity = context
+        .client_identities
+        .as_ref()
+        .and_then(|identities| identities.get(rqctx.request.remote_addr()));
+    // Requests forwarded by the TLS listener come from a local address.
+    let remote_addr = identity
+        .as_ref()
+        .map_or(rqctx.request.remote_addr(), |identity| identity.remote_addr);
+    if let Some(sync_auth) = &context.sync_auth
+        && !sync_auth.is_authorized(rqctx.request.headers())
+    {
+        warn!(
+            rqctx.log,
+            "rejected sync request";
+            "machine_id" => machine_id,
+            "remote_addr" => remote_addr.to_string(),
+        );
+        return Err(HttpError::for_client_error(
+            None,
+            ClientErrorStatusCode::UNAUTHORIZED,
+            "invalid or missing sync token".to_string(),
+        ));
+    }
+    if context.client_identities.is_some()
+        && !identity
+            .as_ref()
+            .is_some_and(|identity| identity.matches(machine_id))
+    {
+        warn!(
+            rqctx.log,
+            "rejected client certificate";
+            "machine_id" => machine_id,
+            "remote_addr" => remote_addr.to_string(),
+            "certificate_names" => identity.map(|identity| identity.names.join(", ")),
+        );
+        return Err(HttpError::for_client_error(
+            None,
+            ClientErrorStatusCode::FORBIDDEN,
+            format!("client certificate was not issued for machine \"{machine_id}\""),
+        ));
     }
+    Ok(())
 }
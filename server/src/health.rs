@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+
+use camino::Utf8PathBuf;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config_store::SharedConfigStore;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct Health {
+    pub status: String,
+}
+
+/// What `/readyz` checks besides the config store.
+#[derive(Debug, Default)]
+pub struct Readiness {
+    pub event_log_path: Option<Utf8PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub checks: BTreeMap<String, ReadinessCheck>,
+    /// Why the last reload of the client configs failed. The configs loaded before keep being
+    /// served, so this does not make the server unready.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub last_reload_error: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct ReadinessCheck {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error: Option<String>,
+}
+
+impl From<Result<(), String>> for ReadinessCheck {
+    fn from(result: Result<(), String>) -> Self {
+        ReadinessCheck {
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+impl Readiness {
+    /// Checks are only reported for the features that are configured.
+    pub fn check(&self, store: &SharedConfigStore) -> ReadinessReport {
+        let mut checks: BTreeMap<String, ReadinessCheck> = BTreeMap::new();
+        // A store can only be created from configs that loaded, and failed reloads keep it, so
+        // there are always configs to serve.
+        checks.insert("config_store".to_string(), Ok(()).into());
+        if let Some(path) = &self.event_log_path {
+            let event_log = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .map(|_| ())
+                .map_err(|e| format!("event log \"{path}\" is not writable: {e}"));
+            checks.insert("event_log".to_string(), event_log.into());
+        }
+        ReadinessReport {
+            ready: checks.values().all(|check| check.ok),
+            checks,
+            last_reload_error: store.last_reload_error(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use slog::Logger;
+
+    use super::*;
+    use crate::config_store::ConfigStore;
+
+    #[test]
+    fn stays_ready_after_a_failed_reload() {
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("chimney-readiness-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::copy(
+            "tests/tomls/client-tomls/good/global.toml",
+            dir.join("global.toml"),
+        )
+        .unwrap();
+        let store = SharedConfigStore::new(ConfigStore::from_path(&dir).unwrap());
+        fs::write(dir.join("machine-id-9999.toml"), "client_mode = ").unwrap();
+        store.reload_and_log(&Logger::root(slog::Discard, slog::o!()), "test");
+
+        let report = Readiness::default().check(&store);
+        assert!(report.ready);
+        assert!(report.checks["config_store"].ok);
+        let error = report.last_reload_error.unwrap();
+        assert!(error.contains("machine-id-9999.toml"), "{error}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config_store::is_safe_file_stem;
+
+const MACHINE_FILE_EXTENSION: &str = "json";
+
+/// What chimney last heard from a machine. Times are seconds since the Unix epoch.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct Machine {
+    pub machine_id: String,
+    /// The client config that was selected for the machine during its last preflight.
+    pub client_config: Option<String>,
+    pub last_preflight: Option<santa_types::PreflightOptions>,
+    pub last_preflight_at: Option<u64>,
+    pub last_postflight: Option<santa_types::PostflightOptions>,
+    pub last_postflight_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct Machines {
+    pub machines: Vec<Machine>,
+}
+
+/// Machine state built from preflight and postflight requests.
+///
+/// When the inventory has a path, every machine is stored as a JSON file named after its machine
+/// id in that directory and the inventory is loaded from it on startup. Files are written on the
+/// blocking thread pool after the in-memory state is updated, so a slow disk never holds up
+/// readers of the inventory or the async runtime.
+#[derive(Debug)]
+pub struct MachineInventory {
+    machines: Arc<RwLock<BTreeMap<String, Machine>>>,
+    path: Option<Utf8PathBuf>,
+    /// Serializes writes to the inventory directory.
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl MachineInventory {
+    pub fn in_memory() -> Self {
+        MachineInventory {
+            machines: Default::default(),
+            path: None,
+            write_lock: Default::default(),
+        }
+    }
+
+    pub fn open<P: AsRef<Utf8Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)?;
+        let mut machines = BTreeMap::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let entry_path = Utf8PathBuf::from_path_buf(entry.path()).expect("valid UTF-8 path");
+            if entry_path.extension() != Some(MACHINE_FILE_EXTENSION) {
+                continue;
+            }
+            let contents = fs::read(&entry_path)?;
+            let machine: Machine = serde_json::from_slice(&contents).map_err(|e| {
+                anyhow::anyhow!(
+                    "could not parse machine inventory \"{}\", {}",
+                    entry_path,
+                    e
+                )
+            })?;
+            machines.insert(machine.machine_id.clone(), machine);
+        }
+        Ok(MachineInventory {
+            machines: Arc::new(RwLock::new(machines)),
+            path: Some(path.to_path_buf()),
+            write_lock: Default::default(),
+        })
+    }
+
+    pub fn get(&self, machine_id: &str) -> Option<Machine> {
+        self.machines.read().unwrap().get(machine_id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Machine> {
+        self.machines.read().unwrap().values().cloned().collect()
+    }
+
+    pub async fn record_preflight(
+        &self,
+        machine_id: &str,
+        preflight: santa_types::PreflightOptions,
+        client_config: &str,
+    ) -> Result<(), io::Error> {
+        self.update(machine_id, |machine| {
+            machine.client_config = Some(client_config.to_string());
+            machine.last_preflight = Some(preflight);
+            machine.last_preflight_at = Some(now());
+        })
+        .await
+    }
+
+    pub async fn record_postflight(
+        &self,
+        machine_id: &str,
+        postflight: santa_types::PostflightOptions,
+    ) -> Result<(), io::Error> {
+        self.update(machine_id, |machine| {
+            machine.last_postflight = Some(postflight);
+            machine.last_postflight_at = Some(now());
+        })
+        .await
+    }
+
+    async fn update<F: FnOnce(&mut Machine)>(
+        &self,
+        machine_id: &str,
+        f: F,
+    ) -> Result<(), io::Error> {
+        {
+            let mut machines = self.machines.write().unwrap();
+            let machine = machines
+                .entry(machine_id.to_string())
+                .or_insert_with(|| Machine {
+                    machine_id: machine_id.to_string(),
+                    ..Default::default()
+                });
+            f(machine);
+        }
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+        let machines = self.machines.clone();
+        let write_lock = self.write_lock.clone();
+        let machine_id = machine_id.to_string();
+        tokio::task::spawn_blocking(move || {
+            let _guard = write_lock.lock().unwrap();
+            // Concurrent updates of a machine may reach this point in any order, so the latest
+            // state is written instead of the one this update made.
+            let machine = machines.read().unwrap().get(&machine_id).cloned();
+            match machine {
+                Some(machine) => persist(&path, &machine),
+                None => Ok(()),
+            }
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+}
+
+/// Writes to a temporary file first so that a crash never leaves a partially written machine.
+fn persist(path: &Utf8Path, machine: &Machine) -> Result<(), io::Error> {
+    if !is_safe_file_stem(&machine.machine_id) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "machine id \"{}\" is not a valid file name",
+                machine.machine_id
+            ),
+        ));
+    }
+    let file_path = path.join(format!("{}.{MACHINE_FILE_EXTENSION}", machine.machine_id));
+    let tmp_path = path.join(format!(".{}.tmp", machine.machine_id));
+    fs::write(&tmp_path, serde_json::to_vec_pretty(machine)?)?;
+    fs::rename(tmp_path, file_path)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::preflight_options;
+
+    fn inventory_dir(test_name: &str) -> Utf8PathBuf {
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("chimney-{}-{}", test_name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn records_preflight_and_postflight() {
+        let inventory = MachineInventory::in_memory();
+        inventory
+            .record_preflight(
+                "machine-id-1234",
+                preflight_options("C02SERIAL", "hostname"),
+                "global",
+            )
+            .await
+            .unwrap();
+        let postflight = santa_types::PostflightOptions {
+            rules_received: Some(10),
+            rules_processed: Some(8),
+        };
+        inventory
+            .record_postflight("machine-id-1234", postflight)
+            .await
+            .unwrap();
+
+        let machine = inventory.get("machine-id-1234").unwrap();
+        assert_eq!(Some("global".to_string()), machine.client_config);
+        assert_eq!("2024.1", machine.last_preflight.unwrap().santa_version);
+        assert!(machine.last_preflight_at.is_some());
+        assert_eq!(Some(8), machine.last_postflight.unwrap().rules_processed);
+        assert!(machine.last_postflight_at.is_some());
+        assert_eq!(1, inventory.list().len());
+    }
+
+    #[tokio::test]
+    async fn persists_machines_across_restarts() {
+        let dir = inventory_dir("persists_machines_across_restarts");
+        let inventory = MachineInventory::open(&dir).unwrap();
+        inventory
+            .record_preflight(
+                "machine-id-1234",
+                preflight_options("C02SERIAL", "hostname"),
+                "global",
+            )
+            .await
+            .unwrap();
+        drop(inventory);
+
+        let inventory = MachineInventory::open(&dir).unwrap();
+        let machine = inventory.get("machine-id-1234").unwrap();
+        assert_eq!("C02SERIAL", machine.last_preflight.unwrap().serial_num);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn persists_the_latest_state_of_concurrent_updates() {
+        let dir = inventory_dir("persists_the_latest_state_of_concurrent_updates");
+        let inventory = Arc::new(MachineInventory::open(&dir).unwrap());
+        let updates = (0..20).map(|rules| {
+            let inventory = inventory.clone();
+            tokio::spawn(async move {
+                let postflight = santa_types::PostflightOptions {
+                    rules_received: Some(rules),
+                    rules_processed: Some(rules),
+                };
+                inventory
+                    .record_postflight("machine-id-1234", postflight)
+                    .await
+                    .unwrap();
+            })
+        });
+        for update in updates.collect::<Vec<_>>() {
+            update.await.unwrap();
+        }
+        let in_memory = inventory.get("machine-id-1234").unwrap();
+        drop(inventory);
+
+        let inventory = MachineInventory::open(&dir).unwrap();
+        let on_disk = inventory.get("machine-id-1234").unwrap();
+        assert_eq!(
+            in_memory.last_postflight.unwrap().rules_processed,
+            on_disk.last_postflight.unwrap().rules_processed
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn errs_on_machine_ids_that_are_not_file_names() {
+        let dir = inventory_dir("errs_on_machine_ids_that_are_not_file_names");
+        let inventory = MachineInventory::open(&dir).unwrap();
+        let result = inventory
+            .record_preflight("..", preflight_options("C02SERIAL", "hostname"), "global")
+            .await;
+        assert!(result.is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,52 +1,176 @@
 #![warn(missing_debug_implementations)]
 
+mod admin_api;
+mod admin_entrypoints;
 mod api;
+mod auth;
 pub mod body;
+mod check;
+mod client_cert;
 mod config_store;
 mod constants;
 mod entrypoints;
+mod health;
+mod inventory;
 mod logging;
+mod metrics;
+mod migrate;
+pub mod response;
 mod server_config;
+mod session;
+mod simulate;
+#[cfg(test)]
+mod test_util;
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::anyhow;
 use dropshot::{
-    ApiDescription, ConfigDropshot, ConfigLogging, ConfigTls, HandlerTaskMode, HttpServer,
-    ServerBuilder,
+    ApiDescription, ConfigDropshot, ConfigLogging, HandlerTaskMode, HttpServer, ServerBuilder,
 };
 
-pub use config_store::{ClientConfig, ConfigStore};
+pub use auth::SyncAuth;
+pub use check::{Problem, Severity, check, check_client_configs};
+pub use client_cert::{ClientIdentities, ClientIdentity, start_tls_listener};
+pub use config_store::{
+    ClientConfig, ClientConfigMode, ClientConfigOverlay, ConfigFormat, ConfigStore,
+    ConfigStoreOptions, EffectiveConfig, EffectiveSources, IdentifierValidation, ImportSummary,
+    PreflightOverrides, PreparedResponses, RuleFile, RulePage, RuleSet, Selector,
+    SharedConfigStore, Sources, VersionRange, import_rules, parse_rule_export, read_rule_export,
+    validate_identifier,
+};
 pub(crate) use constants::CHIMNEY;
+pub use health::{Health, Readiness, ReadinessCheck, ReadinessReport};
+pub use inventory::{Machine, MachineInventory, Machines};
 pub use logging::EventLogging;
-pub use server_config::ServerConfig;
+pub use migrate::{Migration, migrate_moroz};
+pub use server_config::{ServerConfig, SyncAuthConfig, TlsConfig};
+pub use session::SyncSessions;
+pub use simulate::{
+    SimulateOptions, SimulateTls, SyncReport, parse_header, read_events, simulate,
+    simulated_preflight,
+};
 
 pub const DEFAULT_REQUEST_BODY_MAX_BYTES: usize = 104_857_600; // 100 MB
+pub const DEFAULT_RULE_DOWNLOAD_PAGE_SIZE: usize = 1_000;
 
 #[derive(Debug)]
 pub struct Context {
+    /// The client certificates of sync connections. When set, sync requests are only accepted
+    /// with a certificate issued for their machine id.
+    pub client_identities: Option<Arc<ClientIdentities>>,
+    pub decode_limits: body::DecodeLimits,
     pub event_log: Option<slog::Logger>,
-    pub store: ConfigStore,
+    pub inventory: Option<Arc<MachineInventory>>,
+    pub metrics_enabled: bool,
+    pub readiness: Readiness,
+    pub response_compression: response::ResponseCompression,
+    pub store: SharedConfigStore,
+    pub rule_download_page_size: usize,
+    pub sessions: SyncSessions,
+    pub sync_auth: Option<Arc<SyncAuth>>,
 }
 
 pub(crate) enum SantaSyncServerApiImpl {}
 
-pub async fn start_server(
-    server_config: ServerConfig,
-) -> Result<HttpServer<Context>, anyhow::Error> {
+#[derive(Debug)]
+pub struct AdminContext {
+    /// The sync server's machine inventory, if it keeps one.
+    pub inventory: Option<Arc<MachineInventory>>,
+    pub store: SharedConfigStore,
+    pub tokens: Vec<String>,
+    /// Serializes rule changes so concurrent requests cannot overwrite each other's edits.
+    pub edit_lock: Arc<Mutex<()>>,
+}
+
+pub(crate) enum ChimneyAdminApiImpl {}
+
+/// A running sync server.
+#[allow(missing_debug_implementations)]
+pub struct SyncServer {
+    pub server: HttpServer<Context>,
+    /// The address clients connect to. With client certificates, TLS connections are accepted on
+    /// this address and forwarded to `server`, which only listens on the loopback interface.
+    pub local_addr: SocketAddr,
+}
+
+pub async fn start_server(server_config: ServerConfig) -> Result<SyncServer, anyhow::Error> {
     let server = {
+        let client_auth = server_config
+            .tls_config
+            .as_ref()
+            .map(TlsConfig::to_client_auth_config)
+            .transpose()?
+            .flatten()
+            .map(|config| (config, Arc::new(ClientIdentities::default())));
+
         let config = ConfigDropshot {
-            bind_address: server_config.bind_address,
+            bind_address: match client_auth {
+                Some(_) => SocketAddr::from(([127, 0, 0, 1], 0)),
+                None => server_config.bind_address,
+            },
             default_handler_task_mode: HandlerTaskMode::Detached,
             default_request_body_max_bytes: DEFAULT_REQUEST_BODY_MAX_BYTES,
             log_headers: Default::default(),
         };
 
+        // chimney terminates TLS itself when it needs the client certificates.
+        let tls = match client_auth {
+            Some(_) => None,
+            None => server_config
+                .tls_config
+                .as_ref()
+                .map(TlsConfig::to_server_only_config_tls),
+        };
+
+        let readiness = Readiness {
+            event_log_path: server_config.event_log_path.clone(),
+        };
+
         let event_log = server_config
             .event_log_path
             .map(|path| EventLogging::File { path }.to_logger().unwrap());
 
+        let inventory = server_config
+            .inventory_path
+            .map(MachineInventory::open)
+            .transpose()?
+            .map(Arc::new);
+
+        let sync_auth = server_config
+            .sync_auth
+            .as_ref()
+            .map(SyncAuth::from_config)
+            .transpose()?
+            .map(Arc::new);
+
         let api = api();
-        let store = ConfigStore::from_path(server_config.client_config_path)?;
-        let context = Context { event_log, store };
+        let store_options = ConfigStoreOptions {
+            mode: server_config.client_config_mode,
+            identifier_validation: server_config.rule_identifier_validation,
+        };
+        let store = SharedConfigStore::new(ConfigStore::from_path_with_options(
+            server_config.client_config_path,
+            store_options,
+        )?);
+        let context = Context {
+            client_identities: client_auth
+                .as_ref()
+                .map(|(_, identities)| identities.clone()),
+            decode_limits: server_config.decode_limits,
+            event_log,
+            inventory: inventory.clone(),
+            metrics_enabled: server_config.metrics_enabled,
+            readiness,
+            response_compression: server_config.response_compression,
+            store: store.clone(),
+            rule_download_page_size: server_config.rule_download_page_size,
+            sessions: SyncSessions::default(),
+            sync_auth: sync_auth.clone(),
+        };
 
         let log = ConfigLogging::File {
             if_exists: server_config.log_mode,
@@ -55,16 +179,62 @@ pub async fn start_server(
         }
         .to_logger(CHIMNEY)?;
 
-        let tls = server_config.tls_config.map(|config| ConfigTls::AsFile {
-            cert_file: config.cert_file.into(),
-            key_file: config.key_file.into(),
-        });
+        if let Some(admin_config) = server_config.admin_config {
+            let config = ConfigDropshot {
+                bind_address: admin_config.bind_address,
+                ..config.clone()
+            };
+            let context = AdminContext {
+                inventory,
+                store: store.clone(),
+                tokens: admin_config.tokens,
+                edit_lock: Default::default(),
+            };
+            let admin_server = ServerBuilder::new(admin_api(), context, log.clone())
+                .config(config)
+                // Admin clients authenticate with bearer tokens instead of client certificates.
+                .tls(
+                    server_config
+                        .tls_config
+                        .as_ref()
+                        .map(TlsConfig::to_server_only_config_tls),
+                )
+                .start()
+                .map_err(|error| anyhow!("configuring chimney admin server {:#}", error))?;
+            // The admin server shuts down when dropped, so it lives in its own task.
+            tokio::spawn(admin_server);
+        }
 
-        ServerBuilder::new(api, context, log)
+        let server = ServerBuilder::new(api, context, log.clone())
             .config(config)
             .tls(tls)
             .start()
-            .map_err(|error| anyhow!("configuring chimney sync server {:#}", error))?
+            .map_err(|error| anyhow!("configuring chimney sync server {:#}", error))?;
+        let local_addr = match client_auth {
+            Some((tls_config, identities)) => {
+                start_tls_listener(
+                    server_config.bind_address,
+                    tls_config,
+                    server.local_addr(),
+                    identities,
+                    log.clone(),
+                )
+                .await?
+            }
+            None => server.local_addr(),
+        };
+
+        config_store::log_warnings(&store.load(), &log);
+        #[cfg(unix)]
+        config_store::reload_on_sighup(store.clone(), log.clone())?;
+        #[cfg(unix)]
+        if let Some(sync_auth) = sync_auth {
+            auth::reload_on_sighup(sync_auth, log.clone())?;
+        }
+        if let Some(interval) = server_config.client_config_watch_interval {
+            config_store::reload_on_change(store, log, Duration::from_secs(interval));
+        }
+        SyncServer { server, local_addr }
     };
     Ok(server)
 }
@@ -73,3 +243,32 @@ pub fn api() -> ApiDescription<Context> {
     crate::api::santa_sync_server_api_mod::api_description::<SantaSyncServerApiImpl>()
         .expect("implementing chimney sync server API endpoints")
 }
+
+pub fn admin_api() -> ApiDescription<AdminContext> {
+    crate::admin_api::chimney_admin_api_mod::api_description::<ChimneyAdminApiImpl>()
+        .expect("implementing chimney admin API endpoints")
+}
+
+/// The HTTP APIs chimney serves.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Api {
+    Sync,
+    Admin,
+}
+
+/// Writes the OpenAPI document for `api` as pretty-printed JSON.
+pub fn write_openapi(api: Api, out: &mut dyn Write) -> Result<(), anyhow::Error> {
+    let version = semver::Version::parse(env!("CARGO_PKG_VERSION"))?;
+    match api {
+        Api::Sync => crate::api::santa_sync_server_api_mod::stub_api_description()
+            .map_err(|e| anyhow!("{e}"))?
+            .openapi("chimney sync API", version)
+            .write(out)?,
+        Api::Admin => crate::admin_api::chimney_admin_api_mod::stub_api_description()
+            .map_err(|e| anyhow!("{e}"))?
+            .openapi("chimney admin API", version)
+            .write(out)?,
+    }
+    writeln!(out)?;
+    Ok(())
+}
@@ -4,7 +4,10 @@ use std::io::LineWriter;
 use std::path::Path;
 
 use camino::Utf8PathBuf;
-use slog::{Drain, Level, Logger, o};
+use slog::{Drain, Level, Logger, OwnedKVList, Record, o};
+use slog_async::{AsyncCore, AsyncError};
+
+use crate::metrics::METRICS;
 
 #[derive(Debug)]
 pub enum EventLogging {
@@ -45,9 +48,25 @@ where
     <T as slog::Drain>::Err: std::fmt::Debug,
 {
     let level_drain = slog::LevelFilter(drain, Level::Info).fuse();
-    let async_drain = slog_async::Async::new(level_drain)
-        .chan_size(1024)
-        .build()
-        .fuse();
+    let async_drain = CountDropped(AsyncCore::custom(level_drain).chan_size(1024).build()).fuse();
     slog::Logger::root(async_drain, o!())
 }
+
+/// Drops records that do not fit in the async channel and counts them, so that logging never
+/// blocks a request.
+struct CountDropped(AsyncCore);
+
+impl Drain for CountDropped {
+    type Ok = ();
+    type Err = AsyncError;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), AsyncError> {
+        match self.0.log(record, values) {
+            Err(AsyncError::Full) => {
+                METRICS.event_log_dropped_records.inc();
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
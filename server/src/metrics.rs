@@ -0,0 +1,169 @@
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use dropshot::{HttpError, HttpResponse};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+/// Metrics are process wide, because some are recorded where no server context is available,
+/// e.g. while decoding request bodies or writing the event log.
+pub(crate) static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+const RULES_SERVED_BUCKETS: &[f64] = &[0.0, 1.0, 10.0, 100.0, 1_000.0, 10_000.0];
+
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    registry: Registry,
+    pub client_configs: IntGauge,
+    pub decompression_failures: IntCounterVec,
+    pub event_log_dropped_records: IntCounter,
+    pub events_uploaded: IntCounterVec,
+    pub request_duration: HistogramVec,
+    pub requests: IntCounterVec,
+    pub rules_served: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let client_configs = IntGauge::new(
+            "chimney_client_configs",
+            "Number of client configs in the config store.",
+        )
+        .unwrap();
+        let decompression_failures = IntCounterVec::new(
+            Opts::new(
+                "chimney_decompression_failures_total",
+                "Request bodies that could not be decoded, by Content-Encoding.",
+            ),
+            &["content_encoding"],
+        )
+        .unwrap();
+        let event_log_dropped_records = IntCounter::new(
+            "chimney_event_log_dropped_records_total",
+            "Event log records dropped because the event log could not keep up.",
+        )
+        .unwrap();
+        let events_uploaded = IntCounterVec::new(
+            Opts::new(
+                "chimney_events_uploaded_total",
+                "Events uploaded by Santa, by decision.",
+            ),
+            &["decision"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "chimney_request_duration_seconds",
+                "Time spent handling requests, by endpoint.",
+            ),
+            &["endpoint"],
+        )
+        .unwrap();
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "chimney_requests_total",
+                "Requests handled, by endpoint and response status.",
+            ),
+            &["endpoint", "status"],
+        )
+        .unwrap();
+        let rules_served = Histogram::with_opts(
+            HistogramOpts::new(
+                "chimney_rules_served",
+                "Number of rules sent in a ruledownload response.",
+            )
+            .buckets(RULES_SERVED_BUCKETS.to_vec()),
+        )
+        .unwrap();
+
+        registry.register(Box::new(client_configs.clone())).unwrap();
+        registry
+            .register(Box::new(decompression_failures.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(event_log_dropped_records.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(events_uploaded.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(request_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(rules_served.clone())).unwrap();
+
+        Metrics {
+            registry,
+            client_configs,
+            decompression_failures,
+            event_log_dropped_records,
+            events_uploaded,
+            request_duration,
+            requests,
+            rules_served,
+        }
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encoding metrics");
+        String::from_utf8(buffer).expect("metrics are UTF-8")
+    }
+}
+
+/// Runs a request handler and records its latency and response status.
+pub(crate) async fn observe<T, F>(endpoint: &'static str, handler: F) -> Result<T, HttpError>
+where
+    T: HttpResponse,
+    F: Future<Output = Result<T, HttpError>>,
+{
+    let start = Instant::now();
+    let result = handler.await;
+    let status = match &result {
+        Ok(response) => response.status_code(),
+        Err(e) => e.status_code.as_status(),
+    };
+    METRICS
+        .request_duration
+        .with_label_values(&[endpoint])
+        .observe(start.elapsed().as_secs_f64());
+    METRICS
+        .requests
+        .with_label_values(&[endpoint, status.as_str()])
+        .inc();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use dropshot::{ClientErrorStatusCode, HttpResponseOk};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn observes_response_status() {
+        let _ = observe("observes_response_status", async { Ok(HttpResponseOk(())) }).await;
+        let _ = observe::<HttpResponseOk<()>, _>("observes_response_status", async {
+            Err(HttpError::for_client_error(
+                None,
+                ClientErrorStatusCode::UNAUTHORIZED,
+                "unauthorized".to_string(),
+            ))
+        })
+        .await;
+        let metrics = METRICS.encode();
+        assert!(metrics.contains(
+            r#"chimney_requests_total{endpoint="observes_response_status",status="200"} 1"#
+        ));
+        assert!(metrics.contains(
+            r#"chimney_requests_total{endpoint="observes_response_status",status="401"} 1"#
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+use std::fs;
+
+use anyhow::anyhow;
+use camino::{Utf8Path, Utf8PathBuf};
+use toml_edit::{DocumentMut, value};
+
+use crate::check::{Problem, check_client_configs, problem};
+use crate::config_store::{ConfigStoreOptions, ExportedRule, RuleFile, find_config_files, to_rule};
+
+/// The moroz settings chimney has an equivalent for.
+const MOROZ_KEYS: [&str; 15] = [
+    "allowlist_regex",
+    "batch_size",
+    "blacklist_regex",
+    "blocklist_regex",
+    "clean_sync",
+    "client_mode",
+    "enable_bundles",
+    "enable_transitive_rules",
+    "enable_transitive_whitelisting",
+    "enabled_transitive_rules",
+    "enabled_transitive_whitelisting",
+    "full_sync_interval",
+    "machine_id",
+    "rules",
+    "whitelist_regex",
+];
+
+/// The keys a moroz rule can have. Rules are otherwise laid out the way Santa exports them.
+const MOROZ_RULE_KEYS: [&str; 6] = [
+    "custom_msg",
+    "custom_url",
+    "identifier",
+    "policy",
+    "rule_type",
+    "sha256",
+];
+
+/// The client configs written for a moroz config directory.
+#[derive(Debug)]
+pub struct Migration {
+    /// The files written, in the order the moroz files were read.
+    pub files: Vec<Utf8PathBuf>,
+    /// Moroz settings that were left out, because chimney has no equivalent or their values could
+    /// not be converted.
+    pub unmapped: Vec<Problem>,
+    /// Problems `chimney check` finds in the files written.
+    pub problems: Vec<Problem>,
+}
+
+/// Converts the moroz configs in `from` into a `client_config_path` tree in `to`, which must be
+/// empty or not exist yet. Every moroz config is a complete client config that is used instead of
+/// `global.toml`, the same as chimney's replace mode.
+pub fn migrate_moroz(from: &Utf8Path, to: &Utf8Path) -> Result<Migration, anyhow::Error> {
+    if !from.is_dir() {
+        return Err(anyhow!("path {:?} is not a directory", from));
+    }
+    if to.exists() && fs::read_dir(to)?.next().is_some() {
+        return Err(anyhow!("path {:?} is not empty", to));
+    }
+    let mut moroz_files = find_config_files(from)?;
+    moroz_files.sort();
+
+    // Every file is converted before any is written, so a moroz file that cannot be read leaves
+    // `to` untouched and the migration can be run again once it is fixed.
+    let mut converted = vec![];
+    let mut unmapped = vec![];
+    let mut errors = vec![];
+    for moroz_file in moroz_files {
+        if moroz_file.extension() != Some("toml") {
+            unmapped.push(problem(&moroz_file, None, "moroz only reads .toml files"));
+            continue;
+        }
+        let contents = fs::read_to_string(&moroz_file)?;
+        let moroz: toml::Table = match toml::from_str(&contents) {
+            Ok(moroz) => moroz,
+            Err(e) => {
+                errors.push(format!("could not parse TOML \"{moroz_file}\", {e}"));
+                continue;
+            }
+        };
+        let relative = moroz_file
+            .strip_prefix(from)
+            .expect("config files are under the config path");
+        converted.push(convert(
+            &moroz_file,
+            &to.join(relative),
+            moroz,
+            &mut unmapped,
+        )?);
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!("{}", errors.join(", ")));
+    }
+    let mut files = vec![];
+    for file in converted {
+        file.save()
+            .map_err(|e| anyhow!("could not write \"{}\", {}", file.path(), e))?;
+        files.push(file.path().to_path_buf());
+    }
+    let problems = match files.is_empty() {
+        true => vec![problem(from, None, "no moroz configs found")],
+        false => check_client_configs(to, &ConfigStoreOptions::default()),
+    };
+    Ok(Migration {
+        files,
+        unmapped,
+        problems,
+    })
+}
+
+/// Converts one moroz config. Settings that cannot be converted are added to `unmapped`.
+fn convert(
+    moroz_file: &Utf8Path,
+    file: &Utf8Path,
+    moroz: toml::Table,
+    unmapped: &mut Vec<Problem>,
+) -> Result<RuleFile, anyhow::Error> {
+    let mut document = DocumentMut::new();
+    let mut rules = vec![];
+    let mut leave_out = |message: String| unmapped.push(problem(moroz_file, None, message));
+    for (key, moroz_value) in moroz {
+        match (key.as_str(), moroz_value) {
+            ("client_mode", toml::Value::String(mode)) => match mode.to_uppercase().as_str() {
+                mode @ ("MONITOR" | "LOCKDOWN") => document["client_mode"] = value(mode),
+                _ => leave_out(format!("client_mode \"{mode}\" is not MONITOR or LOCKDOWN")),
+            },
+            ("allowlist_regex" | "whitelist_regex", toml::Value::String(regex)) => {
+                if !regex.is_empty() {
+                    document["allowed_path_regex"] = value(regex);
+                }
+            }
+            ("blocklist_regex" | "blacklist_regex", toml::Value::String(regex)) => {
+                if !regex.is_empty() {
+                    document["blocked_path_regex"] = value(regex);
+                }
+            }
+            ("batch_size" | "full_sync_interval", toml::Value::Integer(number)) => {
+                document[key.as_str()] = value(number);
+            }
+            ("enable_bundles", toml::Value::Boolean(enabled)) => {
+                document["enable_bundles"] = value(enabled);
+            }
+            (
+                "enable_transitive_rules"
+                | "enabled_transitive_rules"
+                | "enable_transitive_whitelisting"
+                | "enabled_transitive_whitelisting",
+                toml::Value::Boolean(enabled),
+            ) => document["enable_transitive_rules"] = value(enabled),
+            ("clean_sync", toml::Value::Boolean(clean)) => {
+                if clean {
+                    document["sync_type"] = value("CLEAN");
+                }
+            }
+            ("machine_id", toml::Value::String(machine_id)) => {
+                if moroz_file.file_stem() != Some(machine_id.as_str()) {
+                    leave_out(format!(
+                        "machine_id \"{machine_id}\" does not match the file name, which chimney \
+                         uses as the machine id"
+                    ));
+                }
+            }
+            ("rules", toml::Value::Array(moroz_rules)) => {
+                for (index, moroz_rule) in moroz_rules.into_iter().enumerate() {
+                    match convert_rule(moroz_rule, &mut leave_out) {
+                        Ok(rule) => rules.push(rule),
+                        Err(e) => leave_out(format!("rule {} left out: {}", index + 1, e)),
+                    }
+                }
+            }
+            (key, moroz_value) if MOROZ_KEYS.contains(&key) => {
+                leave_out(format!("{key} = {moroz_value} could not be converted"))
+            }
+            (key, _) => leave_out(format!("{key} has no chimney equivalent")),
+        }
+    }
+    // Moroz cannot override file access actions, so Santa keeps its own setting.
+    document["override_file_access_action"] = value("NONE");
+
+    let mut file = RuleFile::create(file, &document.to_string())?;
+    if !rules.is_empty() {
+        file.set_rules(&rules)?;
+    }
+    Ok(file)
+}
+
+fn convert_rule(
+    moroz_rule: toml::Value,
+    leave_out: &mut impl FnMut(String),
+) -> Result<santa_types::Rule, String> {
+    let toml::Value::Table(mut moroz_rule) = moroz_rule else {
+        return Err("not a table".to_string());
+    };
+    for key in moroz_rule.keys() {
+        if !MOROZ_RULE_KEYS.contains(&key.as_str()) {
+            leave_out(format!("rule key {key} has no chimney equivalent"));
+        }
+    }
+    for key in ["policy", "rule_type"] {
+        if let Some(toml::Value::String(name)) = moroz_rule.get_mut(key) {
+            *name = name.to_uppercase();
+        }
+    }
+    let exported: ExportedRule = toml::Value::Table(moroz_rule)
+        .try_into()
+        .map_err(|e: toml::de::Error| e.message().to_string())?;
+    to_rule(exported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_moroz_configs() {
+        let to = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("chimney-migrate-moroz-{}", std::process::id()));
+        let migration = migrate_moroz(Utf8Path::new("tests/moroz"), &to).unwrap();
+
+        assert_eq!(
+            vec![to.join("C02SERIAL.toml"), to.join("global.toml")],
+            migration.files
+        );
+        assert!(migration.problems.is_empty(), "{:?}", migration.problems);
+        let unmapped = migration
+            .unmapped
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "tests/moroz/C02SERIAL.toml: rule 2 left out: TEAMID identifier \"EQHXZ8M8A\" \
+                 must be 10 letters and digits",
+                "tests/moroz/global.toml: enable_all_event_upload has no chimney equivalent",
+                "tests/moroz/global.toml: rule key comment has no chimney equivalent",
+                "tests/moroz/notes.md: moroz only reads .toml files",
+            ],
+            unmapped
+        );
+
+        let global = crate::ClientConfig::from_file(to.join("global.toml")).unwrap();
+        assert_eq!(
+            santa_types::ClientMode::Lockdown,
+            global.preflight.client_mode
+        );
+        assert_eq!(
+            Some("^(?:/Users)/.*".to_string()),
+            global.preflight.allowed_path_regex
+        );
+        assert_eq!(None, global.preflight.blocked_path_regex);
+        assert!(global.preflight.enable_transitive_rules);
+        assert_eq!(santa_types::SyncType::Clean, global.preflight.sync_type);
+        assert_eq!(2, global.rules.len());
+        assert_eq!(santa_types::Policy::Allowlist, global.rules[1].policy);
+        let machine = crate::ClientConfig::from_file(to.join("C02SERIAL.toml")).unwrap();
+        assert_eq!(
+            santa_types::ClientMode::Monitor,
+            machine.preflight.client_mode
+        );
+        assert_eq!(1, machine.rules.len());
+
+        assert!(migrate_moroz(Utf8Path::new("tests/moroz"), &to).is_err());
+        fs::remove_dir_all(to).unwrap();
+    }
+
+    #[test]
+    fn writes_nothing_if_a_moroz_config_cannot_be_parsed() {
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("chimney-migrate-moroz-bad-{}", std::process::id()));
+        let from = dir.join("moroz");
+        let to = dir.join("chimney");
+        fs::create_dir_all(&from).unwrap();
+        fs::copy("tests/moroz/global.toml", from.join("global.toml")).unwrap();
+        fs::write(from.join("machine.toml"), "client_mode = ").unwrap();
+
+        let err = migrate_moroz(&from, &to).unwrap_err().to_string();
+        assert!(
+            err.starts_with(&format!(
+                "could not parse TOML \"{}\"",
+                from.join("machine.toml")
+            )),
+            "{err}"
+        );
+        assert!(!to.exists());
+
+        fs::write(from.join("machine.toml"), "client_mode = \"MONITOR\"").unwrap();
+        assert_eq!(2, migrate_moroz(&from, &to).unwrap().files.len());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::{Arc, OnceLock};
+
+use bytes::Bytes;
+use dropshot::{
+    ApiEndpointResponse, Body, HttpError, HttpResponse, HttpResponseOk, RequestContext,
+};
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY};
+use http::{HeaderMap, Response, StatusCode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::Context;
+
+pub const DEFAULT_RESPONSE_COMPRESSION_MIN_BYTES: usize = 1_024;
+const CONTENT_TYPE_JSON: &str = "application/json";
+
+/// Compression of sync response bodies. Bodies smaller than `min_bytes` are sent uncompressed,
+/// since compressing them saves little and costs CPU on both ends.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResponseCompression {
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+    #[serde(default = "min_bytes_default")]
+    pub min_bytes: usize,
+}
+
+impl Default for ResponseCompression {
+    fn default() -> Self {
+        ResponseCompression {
+            enabled: enabled_default(),
+            min_bytes: min_bytes_default(),
+        }
+    }
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+fn min_bytes_default() -> usize {
+    DEFAULT_RESPONSE_COMPRESSION_MIN_BYTES
+}
+
+/// A JSON response body that is serialized once and compressed at most once per coding, so it can
+/// be shared by every response that sends it.
+#[derive(Debug)]
+pub struct PreparedBody {
+    json: Bytes,
+    deflate: OnceLock<Bytes>,
+    gzip: OnceLock<Bytes>,
+}
+
+impl PreparedBody {
+    pub fn new<T: Serialize>(body: &T) -> Self {
+        let json = serde_json::to_vec(body).expect("sync responses serialize to JSON");
+        PreparedBody {
+            json: Bytes::from(json),
+            deflate: OnceLock::new(),
+            gzip: OnceLock::new(),
+        }
+    }
+
+    fn encoded(&self, coding: ResponseCoding) -> Bytes {
+        let encoded = match coding {
+            ResponseCoding::Deflate => &self.deflate,
+            ResponseCoding::Gzip => &self.gzip,
+        };
+        encoded
+            .get_or_init(|| Bytes::from(coding.encode(&self.json)))
+            .clone()
+    }
+}
+
+/// A 200 JSON response, compressed with the coding the client prefers from its `Accept-Encoding`
+/// header. The OpenAPI description is the same as `HttpResponseOk<T>`.
+#[derive(Debug)]
+pub struct CompressedResponse<T> {
+    body: Arc<PreparedBody>,
+    coding: Option<ResponseCoding>,
+    min_bytes: usize,
+    body_type: PhantomData<fn() -> T>,
+}
+
+impl<T: JsonSchema + Serialize + Send + Sync + 'static> CompressedResponse<T> {
+    pub fn new(rqctx: &RequestContext<Context>, body: &T) -> Self {
+        Self::prepared(rqctx, Arc::new(PreparedBody::new(body)))
+    }
+
+    /// A response that sends `body`, which must be a serialized `T`.
+    pub fn prepared(rqctx: &RequestContext<Context>, body: Arc<PreparedBody>) -> Self {
+        let compression = &rqctx.context().response_compression;
+        let coding = match compression.enabled {
+            true => negotiate(rqctx.request.headers()),
+            false => None,
+        };
+        CompressedResponse {
+            body,
+            coding,
+            min_bytes: compression.min_bytes,
+            body_type: PhantomData,
+        }
+    }
+}
+
+impl<T: JsonSchema + Serialize + Send + Sync + 'static> HttpResponse for CompressedResponse<T> {
+    fn to_result(self) -> Result<Response<Body>, HttpError> {
+        // The body depends on `Accept-Encoding` even when it is sent uncompressed, so caches must
+        // not serve it to clients that sent a different header.
+        let builder = Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, CONTENT_TYPE_JSON)
+            .header(VARY, ACCEPT_ENCODING.as_str());
+        match self
+            .coding
+            .filter(|_| self.body.json.len() >= self.min_bytes)
+        {
+            Some(coding) => Ok(builder
+                .header(CONTENT_ENCODING, coding.name())
+                .body(Body::from(self.body.encoded(coding)))?),
+            None => Ok(builder.body(Body::from(self.body.json.clone()))?),
+        }
+    }
+
+    fn response_metadata() -> ApiEndpointResponse {
+        HttpResponseOk::<T>::response_metadata()
+    }
+
+    fn status_code(&self) -> StatusCode {
+        StatusCode::OK
+    }
+}
+
+/// Content codings chimney can apply to a response, in order of preference.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ResponseCoding {
+    Gzip,
+    Deflate,
+}
+
+impl ResponseCoding {
+    const ALL: [ResponseCoding; 2] = [ResponseCoding::Gzip, ResponseCoding::Deflate];
+
+    fn name(&self) -> &'static str {
+        match self {
+            ResponseCoding::Deflate => "deflate",
+            ResponseCoding::Gzip => "gzip",
+        }
+    }
+
+    fn encode(&self, body: &[u8]) -> Vec<u8> {
+        let encoded = match self {
+            ResponseCoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).and_then(|()| encoder.finish())
+            }
+            ResponseCoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body).and_then(|()| encoder.finish())
+            }
+        };
+        encoded.expect("writing to a Vec cannot fail")
+    }
+}
+
+/// Picks the supported coding with the highest quality value in the `Accept-Encoding` headers,
+/// preferring gzip on ties. Codings with `q=0` are never picked.
+fn negotiate(headers: &HeaderMap) -> Option<ResponseCoding> {
+    let mut wildcard = None;
+    let mut qualities = [None; ResponseCoding::ALL.len()];
+    let values = headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok());
+    for item in values.flat_map(|value| value.split(',')) {
+        let mut params = item.split(';');
+        let coding = params
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+            .next()
+            .unwrap_or(1.0);
+        match coding.as_str() {
+            "*" => wildcard = Some(quality),
+            "gzip" | "x-gzip" => qualities[0] = Some(quality),
+            "deflate" => qualities[1] = Some(quality),
+            _ => {}
+        }
+    }
+    let mut best: Option<(ResponseCoding, f32)> = None;
+    for (coding, quality) in ResponseCoding::ALL.into_iter().zip(qualities) {
+        let Some(quality) = quality.or(wildcard) else {
+            continue;
+        };
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((coding, quality));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn negotiate_str(accept_encoding: &str) -> Option<ResponseCoding> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_str(accept_encoding).unwrap(),
+        );
+        negotiate(&headers)
+    }
+
+    #[test]
+    fn negotiates_response_coding() {
+        assert_eq!(None, negotiate(&HeaderMap::new()));
+        assert_eq!(None, negotiate_str("identity"));
+        assert_eq!(None, negotiate_str("br, zstd"));
+        assert_eq!(Some(ResponseCoding::Gzip), negotiate_str("deflate, gzip"));
+        assert_eq!(Some(ResponseCoding::Gzip), negotiate_str("*"));
+        assert_eq!(
+            Some(ResponseCoding::Deflate),
+            negotiate_str("gzip;q=0.5, deflate")
+        );
+        assert_eq!(
+            Some(ResponseCoding::Deflate),
+            negotiate_str("GZIP;q=0, *;q=0.1")
+        );
+        assert_eq!(None, negotiate_str("*;q=0"));
+    }
+}
@@ -1,32 +1,57 @@
 use std::env;
+use std::fs::File;
+use std::io::BufReader;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use anyhow::anyhow;
 use camino::{Utf8Path, Utf8PathBuf};
-use dropshot::{ConfigLoggingIfExists, ConfigLoggingLevel};
+use dropshot::{ConfigLoggingIfExists, ConfigLoggingLevel, ConfigTls};
+use rustls::RootCertStore;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
 use serde::{Deserialize, Serialize};
 
+use crate::DEFAULT_RULE_DOWNLOAD_PAGE_SIZE;
+use crate::body::{DecodeLimit, DecodeLimits};
+use crate::config_store::{ClientConfigMode, IdentifierValidation};
+use crate::response::ResponseCompression;
+
 const ENV_VAR_CONFIG: &str = "CHIMNEY_CONFIG";
+const DECODED_BODY_ENDPOINTS: &[&str] = &["eventupload", "postflight", "preflight", "ruledownload"];
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ServerConfig {
+    pub admin_config: Option<AdminConfig>,
     pub bind_address: SocketAddr,
+    #[serde(default)]
+    pub client_config_mode: ClientConfigMode,
     pub client_config_path: Utf8PathBuf,
+    pub client_config_watch_interval: Option<u64>,
+    #[serde(default)]
+    pub decode_limits: DecodeLimits,
     pub event_log_path: Option<Utf8PathBuf>,
+    pub inventory_path: Option<Utf8PathBuf>,
     #[serde(default = "log_level_default")]
     pub log_level: ConfigLoggingLevel,
     #[serde(default = "log_mode")]
     pub log_mode: ConfigLoggingIfExists,
     pub log_path: Utf8PathBuf,
+    #[serde(default)]
+    pub metrics_enabled: bool,
+    #[serde(default)]
+    pub response_compression: ResponseCompression,
+    #[serde(default = "rule_download_page_size_default")]
+    pub rule_download_page_size: usize,
+    #[serde(default)]
+    pub rule_identifier_validation: IdentifierValidation,
+    pub sync_auth: Option<SyncAuthConfig>,
     pub tls_config: Option<TlsConfig>,
 }
 
 impl ServerConfig {
     pub fn from_file<P: AsRef<Utf8Path>>(maybe_path: Option<P>) -> Result<Self, anyhow::Error> {
-        let path = maybe_path
-            .map(|p| p.as_ref().to_owned())
-            .or_else(|| env::var(ENV_VAR_CONFIG).ok().map(Utf8PathBuf::from))
-            .ok_or_else(|| anyhow!("chimney server config path not defined"))?;
+        let path = Self::path(maybe_path)?;
         let contents = std::fs::read_to_string(&path)?;
         let config: Self = match toml::from_str(&contents) {
             Ok(config) => config,
@@ -36,7 +61,15 @@ impl ServerConfig {
         Ok(config)
     }
 
-    fn validate(&self) -> Result<(), anyhow::Error> {
+    /// The config file to read: `maybe_path` if given, otherwise the path in `CHIMNEY_CONFIG`.
+    pub fn path<P: AsRef<Utf8Path>>(maybe_path: Option<P>) -> Result<Utf8PathBuf, anyhow::Error> {
+        maybe_path
+            .map(|p| p.as_ref().to_owned())
+            .or_else(|| env::var(ENV_VAR_CONFIG).ok().map(Utf8PathBuf::from))
+            .ok_or_else(|| anyhow!("chimney server config path not defined"))
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), anyhow::Error> {
         if self.log_path.is_dir() {
             return Err(anyhow!(
                 "log path \"{}\" must include the log filename",
@@ -51,16 +84,138 @@ impl ServerConfig {
                 path
             ));
         }
+        if let Some(path) = &self.inventory_path
+            && path.is_file()
+        {
+            return Err(anyhow!("inventory path \"{}\" must be a directory", path));
+        }
+        if self.client_config_watch_interval == Some(0) {
+            return Err(anyhow!(
+                "client config watch interval must be greater than 0"
+            ));
+        }
+        validate_decode_limit(&self.decode_limits.default)?;
+        for (endpoint, limit) in &self.decode_limits.endpoints {
+            if !DECODED_BODY_ENDPOINTS.contains(&endpoint.as_str()) {
+                return Err(anyhow!(
+                    "unknown decode limits endpoint \"{}\", expected one of {}",
+                    endpoint,
+                    DECODED_BODY_ENDPOINTS.join(", ")
+                ));
+            }
+            validate_decode_limit(limit)?;
+        }
+        if let Some(admin_config) = &self.admin_config {
+            if admin_config.tokens.is_empty() {
+                return Err(anyhow!("admin config requires at least one token"));
+            }
+            if admin_config.tokens.iter().any(|token| token.is_empty()) {
+                return Err(anyhow!("admin config tokens must not be empty"));
+            }
+        }
+        if let Some(sync_auth) = &self.sync_auth
+            && sync_auth.tokens.is_empty()
+            && sync_auth.token_file.is_none()
+        {
+            return Err(anyhow!("sync auth requires tokens or a token file"));
+        }
+        if self.rule_download_page_size == 0 {
+            return Err(anyhow!("rule download page size must be greater than 0"));
+        }
         Ok(())
     }
 }
 
+fn validate_decode_limit(limit: &DecodeLimit) -> Result<(), anyhow::Error> {
+    if limit.max_decoded_bytes == Some(0) {
+        return Err(anyhow!("max decoded bytes must be greater than 0"));
+    }
+    if limit.max_compression_ratio == Some(0) {
+        return Err(anyhow!("max compression ratio must be greater than 0"));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AdminConfig {
+    pub bind_address: SocketAddr,
+    pub tokens: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SyncAuthConfig {
+    /// The request header that carries the token. Tokens in `Authorization` use the `Bearer`
+    /// scheme.
+    #[serde(default = "sync_auth_header_default")]
+    pub header: String,
+    pub token_file: Option<Utf8PathBuf>,
+    #[serde(default)]
+    pub tokens: Vec<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TlsConfig {
     pub cert_file: Utf8PathBuf,
+    /// Clients must present a certificate issued by one of these CAs when set.
+    pub client_ca_file: Option<Utf8PathBuf>,
     pub key_file: Utf8PathBuf,
 }
 
+impl TlsConfig {
+    /// The TLS config for sync connections when clients must present a certificate issued by one
+    /// of the client CAs, or `None` if no client CAs are set.
+    pub fn to_client_auth_config(&self) -> Result<Option<rustls::ServerConfig>, anyhow::Error> {
+        let Some(client_ca_file) = &self.client_ca_file else {
+            return Ok(None);
+        };
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(client_ca_file)? {
+            roots
+                .add(cert)
+                .map_err(|e| anyhow!("invalid client CA \"{}\", {}", client_ca_file, e))?;
+        }
+        if roots.is_empty() {
+            return Err(anyhow!(
+                "client CA file \"{}\" does not contain any certificates",
+                client_ca_file
+            ));
+        }
+        let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+        let certs = read_certs(&self.cert_file)?;
+        let key = read_private_key(&self.key_file)?;
+        let mut config = rustls::ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)?;
+        // The listener forwards the decrypted stream as HTTP/1.1.
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Some(config))
+    }
+
+    /// The server certificate without client authentication.
+    pub fn to_server_only_config_tls(&self) -> ConfigTls {
+        ConfigTls::AsFile {
+            cert_file: self.cert_file.clone().into(),
+            key_file: self.key_file.clone().into(),
+        }
+    }
+}
+
+pub(crate) fn read_certs(path: &Utf8Path) -> Result<Vec<CertificateDer<'static>>, anyhow::Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::certs(&mut reader)
+        .collect::<Result<_, _>>()
+        .map_err(|e| anyhow!("could not read certificates \"{}\", {}", path, e))
+}
+
+pub(crate) fn read_private_key(path: &Utf8Path) -> Result<PrivateKeyDer<'static>, anyhow::Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::pkcs8_private_keys(&mut reader)
+        .next()
+        .ok_or_else(|| anyhow!("no PKCS#8 private key in \"{}\"", path))?
+        .map(PrivateKeyDer::from)
+        .map_err(|e| anyhow!("could not read private key \"{}\", {}", path, e))
+}
+
 fn log_level_default() -> ConfigLoggingLevel {
     ConfigLoggingLevel::Info
 }
@@ -69,6 +224,14 @@ fn log_mode() -> ConfigLoggingIfExists {
     ConfigLoggingIfExists::Append
 }
 
+fn rule_download_page_size_default() -> usize {
+    DEFAULT_RULE_DOWNLOAD_PAGE_SIZE
+}
+
+fn sync_auth_header_default() -> String {
+    http::header::AUTHORIZATION.to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -182,6 +345,18 @@ mod tests {
         assert_eq!(log_mode(), config.log_mode);
         assert!(config.event_log_path.is_none());
         assert!(config.tls_config.is_none());
+        assert!(config.client_config_watch_interval.is_none());
+        assert!(config.inventory_path.is_none());
+        assert!(config.admin_config.is_none());
+        assert!(config.sync_auth.is_none());
+        assert!(!config.metrics_enabled);
+        assert_eq!(DecodeLimits::default(), config.decode_limits);
+        assert_eq!(ResponseCompression::default(), config.response_compression);
+        assert_eq!(ClientConfigMode::Replace, config.client_config_mode);
+        assert_eq!(
+            rule_download_page_size_default(),
+            config.rule_download_page_size
+        );
     }
 
     #[cfg(not(windows))]
@@ -189,7 +364,7 @@ mod tests {
     fn parses_valid_config_file_with_tls() {
         let path = Some("tests/tomls/server-tomls/good/config_tls.toml");
         let config = ServerConfig::from_file(path).unwrap();
-        assert!(config.tls_config.is_some());
+        assert!(config.tls_config.unwrap().client_ca_file.is_none());
     }
 
     #[cfg(not(windows))]
@@ -212,6 +387,167 @@ mod tests {
         assert_eq!(dropshot::ConfigLoggingLevel::Debug, config.log_level);
     }
 
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_rule_download_page_size() {
+        let path = Some("tests/tomls/server-tomls/good/config_rule_download_page_size.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        assert_eq!(50, config.rule_download_page_size);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_client_config_watch_interval() {
+        let path = Some("tests/tomls/server-tomls/good/config_client_config_watch_interval.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        assert_eq!(Some(30), config.client_config_watch_interval);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_client_config_mode() {
+        let path = Some("tests/tomls/server-tomls/good/config_client_config_mode.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        assert_eq!(ClientConfigMode::Merge, config.client_config_mode);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_rule_identifier_validation() {
+        let path = Some("tests/tomls/server-tomls/good/config_rule_identifier_validation.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        assert_eq!(
+            IdentifierValidation::Reject,
+            config.rule_identifier_validation
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_client_ca_file() {
+        let path = Some("tests/tomls/server-tomls/good/config_tls_client_ca.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        assert_eq!(
+            Some(Utf8PathBuf::from("/tmp/tls/ca")),
+            config.tls_config.unwrap().client_ca_file
+        );
+    }
+
+    #[test]
+    fn builds_tls_config_with_client_ca() {
+        let config = TlsConfig {
+            cert_file: "tests/tls/server.pem".into(),
+            client_ca_file: Some("tests/tls/ca.pem".into()),
+            key_file: "tests/tls/server.key".into(),
+        };
+        assert!(config.to_client_auth_config().unwrap().is_some());
+    }
+
+    #[test]
+    fn errs_on_client_ca_without_certificates() {
+        let config = TlsConfig {
+            cert_file: "tests/tls/server.pem".into(),
+            client_ca_file: Some("tests/tls/server.key".into()),
+            key_file: "tests/tls/server.key".into(),
+        };
+        let err_msg = config.to_client_auth_config().unwrap_err().to_string();
+        assert_eq!(
+            "client CA file \"tests/tls/server.key\" does not contain any certificates",
+            err_msg
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_sync_auth() {
+        let path = Some("tests/tomls/server-tomls/good/config_sync_auth.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        let sync_auth = config.sync_auth.unwrap();
+        assert_eq!("X-Chimney-Token", sync_auth.header);
+        assert_eq!(vec!["current-token".to_string()], sync_auth.tokens);
+        assert_eq!(
+            Some(Utf8PathBuf::from("/tmp/chimney-tokens")),
+            sync_auth.token_file
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn errs_on_sync_auth_without_tokens() {
+        let path = Some("tests/tomls/server-tomls/bad/sync_auth.toml");
+        let result = ServerConfig::from_file(path);
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert_eq!("sync auth requires tokens or a token file", err_msg);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_metrics_enabled() {
+        let path = Some("tests/tomls/server-tomls/good/config_metrics_enabled.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        assert!(config.metrics_enabled);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_decode_limits() {
+        let path = Some("tests/tomls/server-tomls/good/config_decode_limits.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        let limits = config.decode_limits;
+        assert_eq!(Some(10_485_760), limits.default.max_decoded_bytes);
+        assert_eq!(Some(100), limits.default.max_compression_ratio);
+        let limit = limits.for_endpoint("eventupload");
+        assert_eq!(Some(524_288_000), limit.max_decoded_bytes);
+        assert_eq!(Some(100), limit.max_compression_ratio);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_response_compression() {
+        let path = Some("tests/tomls/server-tomls/good/config_response_compression.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        assert!(config.response_compression.enabled);
+        assert_eq!(4_096, config.response_compression.min_bytes);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn errs_on_unknown_decode_limits_endpoint() {
+        let path = Some("tests/tomls/server-tomls/bad/decode_limits.toml");
+        let result = ServerConfig::from_file(path);
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert_eq!(
+            "unknown decode limits endpoint \"events\", expected one of eventupload, postflight, preflight, ruledownload",
+            err_msg
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_admin_config() {
+        let path = Some("tests/tomls/server-tomls/good/config_admin_config.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        let admin_config = config.admin_config.unwrap();
+        assert_eq!(
+            "127.0.0.1:8081".parse::<SocketAddr>().unwrap(),
+            admin_config.bind_address
+        );
+        assert_eq!(vec!["help-desk-token".to_string()], admin_config.tokens);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn sets_inventory_path() {
+        let path = Some("tests/tomls/server-tomls/good/config_inventory_path.toml");
+        let config = ServerConfig::from_file(path).unwrap();
+        assert_eq!(
+            Some(Utf8PathBuf::from("/tmp/chimney-inventory")),
+            config.inventory_path
+        );
+    }
+
     #[test]
     fn errs_on_invalid_config_file() {
         let path = Some("tests/tomls/server-tomls/bad/config.toml");
@@ -271,4 +607,34 @@ mod tests {
             err_msg
         );
     }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn errs_on_zero_rule_download_page_size() {
+        let path = Some("tests/tomls/server-tomls/bad/rule_download_page_size.toml");
+        let result = ServerConfig::from_file(path);
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert_eq!("rule download page size must be greater than 0", err_msg);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn errs_on_admin_config_without_tokens() {
+        let path = Some("tests/tomls/server-tomls/bad/admin_config.toml");
+        let result = ServerConfig::from_file(path);
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert_eq!("admin config requires at least one token", err_msg);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn errs_on_empty_admin_token() {
+        let path = Some("tests/tomls/server-tomls/bad/admin_config_empty_token.toml");
+        let result = ServerConfig::from_file(path);
+        assert!(result.is_err());
+        let err_msg = result.unwrap_err().to_string();
+        assert_eq!("admin config tokens must not be empty", err_msg);
+    }
 }
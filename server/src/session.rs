@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Sessions that are not finished by a postflight request are forgotten after this long.
+const SYNC_SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Remembers which client config was selected for a machine during preflight so that the rest of
+/// the sync serves the same config.
+#[derive(Debug, Default)]
+pub struct SyncSessions {
+    sessions: Mutex<Sessions>,
+}
+
+#[derive(Debug)]
+struct Sessions {
+    by_machine: HashMap<String, (String, Instant)>,
+    last_swept: Instant,
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Sessions {
+            by_machine: HashMap::new(),
+            last_swept: Instant::now(),
+        }
+    }
+}
+
+impl SyncSessions {
+    /// Expired sessions are dropped when they are looked up. Sessions of machines that never sync
+    /// again are swept at most once per `SYNC_SESSION_TTL`.
+    pub fn start(&self, machine_id: &str, config_key: String) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.last_swept.elapsed() >= SYNC_SESSION_TTL {
+            sessions
+                .by_machine
+                .retain(|_, (_, started)| started.elapsed() < SYNC_SESSION_TTL);
+            sessions.last_swept = Instant::now();
+        }
+        sessions
+            .by_machine
+            .insert(machine_id.to_string(), (config_key, Instant::now()));
+    }
+
+    pub fn config_key(&self, machine_id: &str) -> Option<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let (config_key, started) = sessions.by_machine.get(machine_id)?;
+        if started.elapsed() < SYNC_SESSION_TTL {
+            return Some(config_key.clone());
+        }
+        sessions.by_machine.remove(machine_id);
+        None
+    }
+
+    pub fn finish(&self, machine_id: &str) {
+        self.sessions.lock().unwrap().by_machine.remove(machine_id);
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use camino::{Utf8Path, Utf8PathBuf};
+use flate2::Compression;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use flate2::write::ZlibEncoder;
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, HOST};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Uri};
+use http_body_util::{BodyExt, Full};
+use hyper::client::conn::http1::{self, SendRequest};
+use hyper_util::rt::TokioIo;
+use rustls::RootCertStore;
+use rustls::pki_types::ServerName;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use crate::server_config::{read_certs, read_private_key};
+
+/// The number of events Santa uploads at a time when preflight does not set `batch_size`.
+const DEFAULT_BATCH_SIZE: u32 = 50;
+
+/// A sync to run against a chimney server, the way a Santa client would.
+#[derive(Clone, Debug)]
+pub struct SimulateOptions {
+    /// The URL of the server, e.g. `https://chimney.example.com:8443`.
+    pub url: Uri,
+    pub machine_id: String,
+    pub preflight: santa_types::PreflightOptions,
+    /// Events to upload after preflight, in batches of the size preflight returns.
+    pub events: Vec<santa_types::Event>,
+    /// Headers sent with every request, like Santa's `SyncExtraHeaders`.
+    pub headers: HeaderMap,
+    /// Required for `https` URLs.
+    pub tls: Option<SimulateTls>,
+}
+
+/// The CA to trust for an `https` server, and the client certificate to present if it asks for
+/// one.
+#[derive(Clone, Debug)]
+pub struct SimulateTls {
+    /// Trusts the Mozilla root certificates bundled with chimney when not set.
+    pub ca_file: Option<Utf8PathBuf>,
+    pub client_cert_file: Option<Utf8PathBuf>,
+    pub client_key_file: Option<Utf8PathBuf>,
+}
+
+/// What the server sent back during a simulated sync.
+#[derive(Debug)]
+pub struct SyncReport {
+    pub preflight: santa_types::Preflight,
+    pub events_uploaded: usize,
+    pub eventupload_requests: usize,
+    pub rules: Vec<santa_types::Rule>,
+    pub ruledownload_requests: usize,
+}
+
+/// A preflight request for a Santa client in monitor mode that has no rules yet.
+pub fn simulated_preflight() -> santa_types::PreflightOptions {
+    santa_types::PreflightOptions {
+        serial_num: "CHIMNEYSIM".to_string(),
+        hostname: "chimney-simulate".to_string(),
+        os_version: "14.4.1".to_string(),
+        os_build: "23E224".to_string(),
+        model_identifier: None,
+        santa_version: "2024.1".to_string(),
+        primary_user: None,
+        binary_rule_count: None,
+        certificate_rule_count: None,
+        compiler_rule_count: None,
+        transitive_rule_count: None,
+        teamid_rule_count: None,
+        signingid_rule_count: None,
+        cdhash_rule_count: None,
+        client_mode: santa_types::ClientMode::Monitor,
+        request_clean_sync: false,
+    }
+}
+
+/// Reads recorded events from an `eventupload` request body.
+pub fn read_events<P: AsRef<Utf8Path>>(path: P) -> Result<Vec<santa_types::Event>, anyhow::Error> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow!("could not read events \"{}\", {}", path, e))?;
+    let upload: santa_types::EventUploadOptions = serde_json::from_str(&contents)
+        .map_err(|e| anyhow!("could not parse events \"{}\", {}", path, e))?;
+    Ok(upload.events)
+}
+
+/// Parses a header given as `Name: value`.
+pub fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), anyhow::Error> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| anyhow!("header \"{}\" must be \"Name: value\"", header))?;
+    let name = HeaderName::try_from(name.trim())
+        .map_err(|e| anyhow!("invalid header name \"{}\", {}", name.trim(), e))?;
+    let value = HeaderValue::try_from(value.trim())
+        .map_err(|e| anyhow!("invalid value for header \"{}\", {}", name, e))?;
+    Ok((name, value))
+}
+
+/// Runs preflight, eventupload, ruledownload and postflight against a chimney server, with request
+/// bodies compressed the way Santa compresses them. Every rule page is downloaded, following the
+/// cursors the server returns.
+pub async fn simulate(options: &SimulateOptions) -> Result<SyncReport, anyhow::Error> {
+    let mut client = SyncClient::connect(options).await?;
+    let preflight: santa_types::Preflight = client.post("preflight", &options.preflight).await?;
+
+    let batch_size = preflight.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1) as usize;
+    let mut eventupload_requests = 0;
+    for batch in options.events.chunks(batch_size) {
+        let upload = santa_types::EventUploadOptions {
+            events: batch.to_vec(),
+        };
+        let _: santa_types::Empty = client.post("eventupload", &upload).await?;
+        eventupload_requests += 1;
+    }
+
+    let mut rules = vec![];
+    let mut ruledownload_requests = 0;
+    let mut cursor = None;
+    loop {
+        let download = santa_types::RuleDownloadOptions {
+            cursor: cursor.clone(),
+        };
+        let page: santa_types::Rules = client.post("ruledownload", &download).await?;
+        ruledownload_requests += 1;
+        rules.extend(page.rules);
+        match page.cursor {
+            Some(next) if cursor.as_ref() == Some(&next) => {
+                return Err(anyhow!(
+                    "ruledownload returned the cursor \"{}\" again",
+                    next
+                ));
+            }
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    let rule_count = u32::try_from(rules.len()).ok();
+    let postflight = santa_types::PostflightOptions {
+        rules_received: rule_count,
+        rules_processed: rule_count,
+    };
+    let _: santa_types::Empty = client.post("postflight", &postflight).await?;
+
+    Ok(SyncReport {
+        preflight,
+        events_uploaded: options.events.len(),
+        eventupload_requests,
+        rules,
+        ruledownload_requests,
+    })
+}
+
+/// One connection to the server, which every request in a sync is sent over.
+struct SyncClient<'a> {
+    authority: String,
+    base_path: String,
+    options: &'a SimulateOptions,
+    sender: SendRequest<Full<Bytes>>,
+}
+
+impl<'a> SyncClient<'a> {
+    async fn connect(options: &'a SimulateOptions) -> Result<Self, anyhow::Error> {
+        let url = &options.url;
+        let host = url
+            .host()
+            .ok_or_else(|| anyhow!("URL \"{}\" has no host", url))?;
+        let https = match url.scheme_str() {
+            Some("http") => false,
+            Some("https") => true,
+            _ => {
+                return Err(anyhow!(
+                    "URL \"{}\" must start with http:// or https://",
+                    url
+                ));
+            }
+        };
+        let client_cert = options
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.client_cert_file.is_some());
+        if !https && client_cert {
+            return Err(anyhow!("client certificates require an https URL"));
+        }
+        if https && options.tls.is_none() {
+            return Err(anyhow!("https URLs require a CA to trust"));
+        }
+        let port = url.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let stream = TcpStream::connect((host.trim_matches(['[', ']']), port))
+            .await
+            .map_err(|e| anyhow!("could not connect to {}, {}", url, e))?;
+        let sender = match (https, &options.tls) {
+            (true, Some(tls)) => {
+                let connector = TlsConnector::from(Arc::new(client_tls_config(tls)?));
+                let domain = ServerName::try_from(host.trim_matches(['[', ']']).to_string())?;
+                handshake(connector.connect(domain, stream).await?).await?
+            }
+            _ => handshake(stream).await?,
+        };
+        Ok(SyncClient {
+            authority: url
+                .authority()
+                .map(|authority| authority.to_string())
+                .unwrap_or_else(|| host.to_string()),
+            base_path: url.path().trim_end_matches('/').to_string(),
+            options,
+            sender,
+        })
+    }
+
+    async fn post<T: Serialize, R: DeserializeOwned>(
+        &mut self,
+        endpoint: &str,
+        body: &T,
+    ) -> Result<R, anyhow::Error> {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(format!(
+                "{}/{}/{}",
+                self.base_path, endpoint, self.options.machine_id
+            ))
+            .header(HOST, &self.authority)
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_ENCODING, "deflate")
+            .header(ACCEPT_ENCODING, "deflate, gzip")
+            .body(Full::new(Bytes::from(deflate(&serde_json::to_vec(body)?))))?;
+        request.headers_mut().extend(self.options.headers.clone());
+
+        self.sender.ready().await?;
+        let response = self.sender.send_request(request).await?;
+        let status = response.status();
+        let content_encoding = response.headers().get(CONTENT_ENCODING).cloned();
+        let body = response.into_body().collect().await?.to_bytes();
+        let body = match content_encoding.as_ref().map(HeaderValue::to_str) {
+            None => body.to_vec(),
+            Some(Ok("deflate")) => decode(ZlibDecoder::new(&body[..]))?,
+            Some(Ok("gzip")) => decode(MultiGzDecoder::new(&body[..]))?,
+            Some(content_encoding) => {
+                return Err(anyhow!(
+                    "{} response has unsupported Content-Encoding {:?}",
+                    endpoint,
+                    content_encoding
+                ));
+            }
+        };
+        if !status.is_success() {
+            return Err(anyhow!(
+                "{} returned {}: {}",
+                endpoint,
+                status,
+                String::from_utf8_lossy(&body)
+            ));
+        }
+        serde_json::from_slice(&body)
+            .map_err(|e| anyhow!("could not parse {} response, {}", endpoint, e))
+    }
+}
+
+async fn handshake<S>(stream: S) -> Result<SendRequest<Full<Bytes>>, anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (sender, connection) = http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(connection);
+    Ok(sender)
+}
+
+fn client_tls_config(tls: &SimulateTls) -> Result<rustls::ClientConfig, anyhow::Error> {
+    let mut roots = RootCertStore::empty();
+    match &tls.ca_file {
+        Some(ca_file) => {
+            for cert in read_certs(ca_file)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| anyhow!("invalid CA \"{}\", {}", ca_file, e))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+    let mut config = match (&tls.client_cert_file, &tls.client_key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            builder.with_client_auth_cert(read_certs(cert_file)?, read_private_key(key_file)?)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(anyhow!(
+                "a client certificate and its key must be given together"
+            ));
+        }
+    };
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// Santa compresses request bodies with zlib and sends them as `Content-Encoding: deflate`.
+fn deflate(body: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(body)
+        .and_then(|()| encoder.finish())
+        .expect("writing to a Vec cannot fail")
+}
+
+fn decode<R: Read>(mut decoder: R) -> Result<Vec<u8>, anyhow::Error> {
+    let mut decoded = vec![];
+    decoder.read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_headers() {
+        let (name, value) = parse_header("X-Chimney-Token: secret").unwrap();
+        assert_eq!("x-chimney-token", name.as_str());
+        assert_eq!("secret", value);
+        assert!(parse_header("X-Chimney-Token").is_err());
+        assert!(parse_header("Bad Header: secret").is_err());
+    }
+
+    #[test]
+    fn presents_client_certificates_without_a_ca_file() {
+        let tls = SimulateTls {
+            ca_file: None,
+            client_cert_file: Some("tests/tls/client.pem".into()),
+            client_key_file: Some("tests/tls/client.key".into()),
+        };
+        let config = client_tls_config(&tls).unwrap();
+        assert!(config.client_auth_cert_resolver.has_certs());
+    }
+
+    #[tokio::test]
+    async fn rejects_unusable_tls_settings() {
+        let options = SimulateOptions {
+            url: "http://127.0.0.1:1".parse().unwrap(),
+            machine_id: "machine-id-1234".to_string(),
+            preflight: simulated_preflight(),
+            events: vec![],
+            headers: HeaderMap::new(),
+            tls: Some(SimulateTls {
+                ca_file: None,
+                client_cert_file: Some("tests/tls/client.pem".into()),
+                client_key_file: Some("tests/tls/client.key".into()),
+            }),
+        };
+        let err = simulate(&options).await.unwrap_err();
+        assert_eq!("client certificates require an https URL", err.to_string());
+
+        let options = SimulateOptions {
+            url: "https://127.0.0.1:1".parse().unwrap(),
+            tls: None,
+            ..options
+        };
+        let err = simulate(&options).await.unwrap_err();
+        assert_eq!("https URLs require a CA to trust", err.to_string());
+    }
+}
@@ -0,0 +1,13 @@
+/// A preflight request body from Santa 2024.1 on macOS 14.4.1.
+pub(crate) fn preflight_options(serial_num: &str, hostname: &str) -> santa_types::PreflightOptions {
+    serde_json::from_value(serde_json::json!({
+        "serial_num": serial_num,
+        "hostname": hostname,
+        "os_version": "14.4.1",
+        "os_build": "23E224",
+        "santa_version": "2024.1",
+        "primary_user": "alice",
+        "client_mode": "MONITOR"
+    }))
+    .unwrap()
+}
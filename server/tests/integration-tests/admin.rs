@@ -0,0 +1,314 @@
+use std::fs;
+
+use camino::Utf8PathBuf;
+use dropshot::test_util::TestContext as DropshotTestContext;
+use dropshot::test_util::read_json;
+use dropshot::{Body, ConfigDropshot};
+use http::Method;
+use http::header::{AUTHORIZATION, CONTENT_TYPE};
+
+use chimney_server::{
+    AdminContext, ConfigStore, ConfigStoreOptions, IdentifierValidation, SharedConfigStore,
+    admin_api,
+};
+
+use crate::test_util::{DEFAULT_CONFIG_PATH, MachineId, create_log_context};
+
+const FORMATS_CONFIG_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/tomls/client-tomls/admin-formats"
+);
+const PREFIX_URI: &str = "/admin/rules";
+pub const TOKEN: &str = "help-desk-token";
+
+pub struct AdminTestContext {
+    pub inner: DropshotTestContext<AdminContext>,
+    config_path: Utf8PathBuf,
+}
+
+impl AdminTestContext {
+    /// Serves the admin API over a copy of the good client configs, so tests can change them.
+    pub fn new(test_name: &str) -> Self {
+        Self::new_with(test_name, DEFAULT_CONFIG_PATH, |_| {})
+    }
+
+    /// Serves the admin API over a copy of the client configs in `source_path`, and allows a test
+    /// to adjust the admin context before the server starts.
+    pub fn new_with<F: FnOnce(&mut AdminContext)>(
+        test_name: &str,
+        source_path: &str,
+        configure: F,
+    ) -> Self {
+        let config_path = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("chimney-{}-{}", test_name, std::process::id()));
+        let _ = fs::remove_dir_all(&config_path);
+        fs::create_dir_all(&config_path).unwrap();
+        for entry in fs::read_dir(source_path).unwrap() {
+            let entry = entry.unwrap();
+            let file_name = entry.file_name();
+            fs::copy(entry.path(), config_path.join(file_name.to_str().unwrap())).unwrap();
+        }
+        let mut context = AdminContext {
+            inventory: None,
+            store: SharedConfigStore::new(ConfigStore::from_path(&config_path).unwrap()),
+            tokens: vec![TOKEN.to_string()],
+            edit_lock: Default::default(),
+        };
+        configure(&mut context);
+        let logctx = create_log_context(test_name);
+        let log = logctx.log.clone();
+        let inner = DropshotTestContext::new(
+            admin_api(),
+            context,
+            &ConfigDropshot::default(),
+            Some(logctx),
+            log,
+        );
+        AdminTestContext { inner, config_path }
+    }
+
+    fn request(
+        &self,
+        method: Method,
+        query: &str,
+        body: &str,
+        token: &str,
+    ) -> hyper::Request<Body> {
+        let uri = self
+            .inner
+            .client_testctx
+            .url(&format!("{PREFIX_URI}?{query}"));
+        hyper::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::with_content(body.to_string()))
+            .expect("invalid request")
+    }
+
+    /// Sends an authorized request; dropshot asserts the response has the expected status.
+    async fn send(
+        &self,
+        method: Method,
+        query: &str,
+        body: &str,
+        status: http::StatusCode,
+    ) -> Option<hyper::Response<Body>> {
+        let request = self.request(method, query, body, TOKEN);
+        self.inner
+            .client_testctx
+            .make_request_with_request(request, status)
+            .await
+            .ok()
+    }
+
+    pub async fn teardown(self) {
+        self.inner.teardown().await;
+        fs::remove_dir_all(self.config_path).unwrap();
+    }
+}
+
+const RULE: &str = r#"{
+    "rule_type": "TEAMID",
+    "policy": "ALLOWLIST",
+    "identifier": "ABCDE12345",
+    "custom_msg": "help desk exception"
+}"#;
+
+#[tokio::test]
+async fn admin_rejects_invalid_token() {
+    let testctx = AdminTestContext::new("admin_rejects_invalid_token");
+    let request = testctx.request(Method::GET, "scope=global", "", "wrong-token");
+    testctx
+        .inner
+        .client_testctx
+        .make_request_with_request(request, http::StatusCode::UNAUTHORIZED)
+        .await
+        .expect_err("expected unauthorized");
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn admin_manages_machine_rules() {
+    let testctx = AdminTestContext::new("admin_manages_machine_rules");
+    let machine_id = "machine-id-9999";
+    let scope = format!("scope=machine&name={machine_id}");
+    let store = &testctx.inner.server.app_private().store;
+
+    testctx
+        .send(Method::GET, &scope, "", http::StatusCode::NOT_FOUND)
+        .await;
+    testctx
+        .send(Method::POST, &scope, RULE, http::StatusCode::CREATED)
+        .await;
+    testctx
+        .send(Method::POST, &scope, RULE, http::StatusCode::CONFLICT)
+        .await;
+    let rules = store.config_for(machine_id).rules.clone();
+    assert_eq!(1, rules.len());
+    assert_eq!("ABCDE12345", rules[0].identifier);
+    assert!(testctx.config_path.join("machine-id-9999.toml").is_file());
+
+    let updated = RULE.replace("ALLOWLIST", "BLOCKLIST");
+    testctx
+        .send(Method::PUT, &scope, &updated, http::StatusCode::OK)
+        .await;
+    let mut response = testctx
+        .send(Method::GET, &scope, "", http::StatusCode::OK)
+        .await
+        .unwrap();
+    let rules = read_json::<santa_types::Rules>(&mut response).await;
+    assert_eq!(santa_types::Policy::Blocklist, rules.rules[0].policy);
+
+    let key = format!("{scope}&rule_type=TEAMID&identifier=ABCDE12345");
+    testctx
+        .send(Method::DELETE, &key, "", http::StatusCode::NO_CONTENT)
+        .await;
+    testctx
+        .send(Method::DELETE, &key, "", http::StatusCode::NOT_FOUND)
+        .await;
+    assert!(store.config_for(machine_id).rules.is_empty());
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn admin_adds_global_rule() {
+    let testctx = AdminTestContext::new("admin_adds_global_rule");
+    let store = &testctx.inner.server.app_private().store;
+    testctx
+        .send(
+            Method::POST,
+            "scope=global",
+            RULE,
+            http::StatusCode::CREATED,
+        )
+        .await;
+    let rules = store.config_for("unknown-machine").rules.clone();
+    assert!(rules.iter().any(|rule| rule.identifier == "ABCDE12345"));
+    // Machine specific configs replace global.toml unless the store merges them.
+    let rules = store.config_for(&MachineId::One.to_string()).rules.clone();
+    assert!(!rules.iter().any(|rule| rule.identifier == "ABCDE12345"));
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn admin_rejects_malformed_identifiers() {
+    let testctx = AdminTestContext::new_with(
+        "admin_rejects_malformed_identifiers",
+        DEFAULT_CONFIG_PATH,
+        |context| {
+            let store = context.store.load();
+            let options = ConfigStoreOptions {
+                identifier_validation: IdentifierValidation::Reject,
+                ..store.options.clone()
+            };
+            let store = ConfigStore::from_path_with_options(&store.path, options).unwrap();
+            context.store = SharedConfigStore::new(store);
+        },
+    );
+    let store = &testctx.inner.server.app_private().store;
+    let rule = RULE.replace("ABCDE12345", "ABCDE1234");
+    testctx
+        .send(
+            Method::POST,
+            "scope=global",
+            &rule,
+            http::StatusCode::BAD_REQUEST,
+        )
+        .await;
+    let rules = store.config_for("unknown-machine").rules.clone();
+    assert!(!rules.iter().any(|rule| rule.identifier == "ABCDE1234"));
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn admin_rejects_invalid_scopes() {
+    let testctx = AdminTestContext::new("admin_rejects_invalid_scopes");
+    testctx
+        .send(
+            Method::POST,
+            "scope=machine&name=..%2Fescape",
+            RULE,
+            http::StatusCode::BAD_REQUEST,
+        )
+        .await;
+    testctx
+        .send(
+            Method::POST,
+            "scope=group&name=engineering",
+            RULE,
+            http::StatusCode::BAD_REQUEST,
+        )
+        .await;
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn admin_manages_rules_in_json_and_yaml_configs() {
+    let testctx = AdminTestContext::new_with(
+        "admin_manages_rules_in_json_and_yaml_configs",
+        FORMATS_CONFIG_PATH,
+        |_| {},
+    );
+    let store = &testctx.inner.server.app_private().store;
+
+    testctx
+        .send(
+            Method::POST,
+            "scope=global",
+            RULE,
+            http::StatusCode::CREATED,
+        )
+        .await;
+    let rules = store.config_for("unknown-machine").rules.clone();
+    assert!(rules.iter().any(|rule| rule.identifier == "ABCDE12345"));
+    let global = fs::read_to_string(testctx.config_path.join("global.json")).unwrap();
+    assert!(global.contains("help desk exception"));
+
+    let scope = format!("scope=machine&name={}", MachineId::Two);
+    let key = format!("{scope}&rule_type=TEAMID&identifier=EQHXZ8M8AV");
+    testctx
+        .send(Method::DELETE, &key, "", http::StatusCode::NO_CONTENT)
+        .await;
+    let rules = store.config_for(&MachineId::Two.to_string()).rules.clone();
+    assert_eq!(1, rules.len());
+    assert_eq!(
+        Some(100),
+        store
+            .config_for(&MachineId::Two.to_string())
+            .preflight
+            .batch_size
+    );
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn admin_copies_a_json_global_config_for_new_machines() {
+    let testctx = AdminTestContext::new_with(
+        "admin_copies_a_json_global_config_for_new_machines",
+        FORMATS_CONFIG_PATH,
+        |_| {},
+    );
+    let store = &testctx.inner.server.app_private().store;
+    let machine_id = "machine-id-9999";
+
+    testctx
+        .send(
+            Method::POST,
+            &format!("scope=machine&name={machine_id}"),
+            RULE,
+            http::StatusCode::CREATED,
+        )
+        .await;
+    assert!(testctx.config_path.join("machine-id-9999.json").is_file());
+    let config = store.config_for(machine_id);
+    // The new file is a copy of global.json with the rule added.
+    assert_eq!(3, config.rules.len());
+    assert_eq!(Some(100), config.preflight.batch_size);
+
+    testctx.teardown().await;
+}
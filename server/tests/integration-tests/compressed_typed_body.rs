@@ -11,10 +11,14 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use slog::o;
 
-use crate::test_util::{create_log_context, gzip_encode, zlib_encode};
-use chimney_server::body::CompressedTypedBody;
-
-fn api() -> ApiDescription<usize> {
+#[cfg(feature = "br")]
+use crate::test_util::brotli_encode;
+#[cfg(feature = "zstd")]
+use crate::test_util::zstd_encode;
+use crate::test_util::{create_log_context, gzip_encode, gzip_encode_bytes, zlib_encode};
+use chimney_server::body::{CompressedTypedBody, DecodeLimits};
+
+fn api() -> ApiDescription<DecodeLimits> {
     let mut api = ApiDescription::new();
     api.register(api_compressed_typed_body).unwrap();
     api
@@ -25,10 +29,10 @@ fn api() -> ApiDescription<usize> {
     path = "/echo",
 }]
 async fn api_compressed_typed_body(
-    _rqctx: RequestContext<usize>,
+    rqctx: RequestContext<DecodeLimits>,
     body: CompressedTypedBody<TestRequest>,
 ) -> Result<Response<Body>, HttpError> {
-    let serialized = serde_json::to_string(&body.into_inner()).unwrap();
+    let serialized = serde_json::to_string(&body.read(&rqctx).await?).unwrap();
     let body = Bytes::copy_from_slice(serialized.as_bytes());
     let response = Response::builder()
         .status(StatusCode::OK)
@@ -109,6 +113,47 @@ async fn decodes_gzip_request() {
     testctx.teardown().await;
 }
 
+#[cfg(feature = "br")]
+#[tokio::test]
+async fn decodes_brotli_request() {
+    let testctx = setup_test("decodes_brotli_request", api());
+    let test_req = test_request();
+    let body = echo(&testctx, "br", brotli_encode(&test_req)).await;
+    assert_eq!(test_req, body);
+    testctx.teardown().await;
+}
+
+#[cfg(feature = "zstd")]
+#[tokio::test]
+async fn decodes_zstd_request() {
+    let testctx = setup_test("decodes_zstd_request", api());
+    let test_req = test_request();
+    let body = echo(&testctx, "zstd", zstd_encode(&test_req)).await;
+    assert_eq!(test_req, body);
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn decodes_content_encoding_chain() {
+    let testctx = setup_test("decodes_content_encoding_chain", api());
+    let test_req = test_request();
+    // Deflate first, then gzip over the deflated bytes.
+    let encoded = gzip_encode_bytes(&zlib_encode(&test_req));
+    let body = echo(&testctx, "deflate, identity, gzip", encoded).await;
+    assert_eq!(test_req, body);
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn decodes_identity_request() {
+    let testctx = setup_test("decodes_identity_request", api());
+    let test_req = test_request();
+    let serialized = serde_json::to_vec(&test_req).unwrap();
+    let body = echo(&testctx, "identity", serialized).await;
+    assert_eq!(test_req, body);
+    testctx.teardown().await;
+}
+
 #[tokio::test]
 async fn uncompressed_request() {
     let api = api();
@@ -229,7 +274,7 @@ async fn invalid_deflate_request() {
 
     let response = testctx
         .client_testctx
-        .make_request_with_request(request, http::StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        .make_request_with_request(request, http::StatusCode::BAD_REQUEST)
         .await;
     let err = response.unwrap_err();
     assert_eq!(
@@ -263,7 +308,7 @@ async fn invalid_gzip_request() {
 
     let response = testctx
         .client_testctx
-        .make_request_with_request(request, http::StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        .make_request_with_request(request, http::StatusCode::BAD_REQUEST)
         .await;
     let err = response.unwrap_err();
     assert_eq!(err.message, "not a gzip request body: invalid gzip header");
@@ -271,7 +316,36 @@ async fn invalid_gzip_request() {
     testctx.teardown().await;
 }
 
-fn setup_test(test_name: &str, api: ApiDescription<usize>) -> TestContext<usize> {
+fn test_request() -> TestRequest {
+    TestRequest {
+        field1: true,
+        field2: 20,
+        field3: "test".into(),
+    }
+}
+
+async fn echo(
+    testctx: &TestContext<DecodeLimits>,
+    content_encoding: &str,
+    body: Vec<u8>,
+) -> TestRequest {
+    let uri = testctx.client_testctx.url("/echo");
+    let request = hyper::Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(CONTENT_TYPE, "application/json")
+        .header(CONTENT_ENCODING, content_encoding)
+        .body(Body::with_content(body))
+        .expect("invalid request");
+    let mut response = testctx
+        .client_testctx
+        .make_request_with_request(request, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    read_json::<TestRequest>(&mut response).await
+}
+
+fn setup_test(test_name: &str, api: ApiDescription<DecodeLimits>) -> TestContext<DecodeLimits> {
     let default_handler_task_mode = HandlerTaskMode::Detached;
     let config_dropshot: ConfigDropshot = ConfigDropshot {
         default_handler_task_mode,
@@ -279,5 +353,11 @@ fn setup_test(test_name: &str, api: ApiDescription<usize>) -> TestContext<usize>
     };
     let logctx = create_log_context(test_name);
     let log = logctx.log.new(o!());
-    TestContext::new(api, 0_usize, &config_dropshot, Some(logctx), log)
+    TestContext::new(
+        api,
+        DecodeLimits::default(),
+        &config_dropshot,
+        Some(logctx),
+        log,
+    )
 }
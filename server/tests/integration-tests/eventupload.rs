@@ -1,5 +1,7 @@
 use std::time::Duration;
 
+use chimney_server::body::DecodeLimit;
+
 use crate::test_util::{
     ContentEncoding, DEFAULT_CONFIG_PATH, EventLogMode, MachineId, TestContext, build_request,
 };
@@ -245,3 +247,83 @@ async fn eventupload_deflate_multiple_events() {
 
     testctx.teardown().await;
 }
+
+#[tokio::test]
+async fn eventupload_decoded_body_too_large() {
+    let machine_id = MachineId::One.to_string();
+    let testctx = TestContext::new_with(
+        "eventupload_decoded_body_too_large",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::Persist,
+        |context| {
+            context.decode_limits.endpoints.insert(
+                "eventupload".to_string(),
+                DecodeLimit {
+                    max_compression_ratio: None,
+                    max_decoded_bytes: Some(256),
+                },
+            );
+        },
+    );
+    let uri = testctx.inner.client_testctx.url(&build_uri(&machine_id));
+    let request = build_request(DEFAULT_REQUEST_BODY, &ContentEncoding::Gzip, uri);
+
+    let err = testctx
+        .inner
+        .client_testctx
+        .make_request_with_request(request, http::StatusCode::PAYLOAD_TOO_LARGE)
+        .await
+        .expect_err("expected payload too large");
+    assert_eq!("decoded request body exceeds 256 bytes", err.message);
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn eventupload_malformed_event() {
+    let machine_id = MachineId::One.to_string();
+    let testctx = TestContext::new(
+        "eventupload_malformed_event",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::Persist,
+    );
+    let request_body = r#"{"events": [{"file_sha256": 1}]}"#;
+    let uri = testctx.inner.client_testctx.url(&build_uri(&machine_id));
+    let request = build_request(request_body, &ContentEncoding::Deflate, uri);
+
+    let err = testctx
+        .inner
+        .client_testctx
+        .make_request_with_request(request, http::StatusCode::BAD_REQUEST)
+        .await
+        .expect_err("expected bad request");
+    assert!(err.message.starts_with("unable to parse JSON body"));
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn eventupload_rejected_upload_logs_nothing() {
+    let machine_id = MachineId::One.to_string();
+    let testctx = TestContext::new(
+        "eventupload_rejected_upload_logs_nothing",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::Persist,
+    );
+    // A valid event followed by a malformed one.
+    let request_body = DEFAULT_REQUEST_BODY.replace("}]\n}", r#"}, {"file_sha256": 1}]}"#);
+    let uri = testctx.inner.client_testctx.url(&build_uri(&machine_id));
+    let request = build_request(&request_body, &ContentEncoding::Deflate, uri);
+
+    testctx
+        .inner
+        .client_testctx
+        .make_request_with_request(request, http::StatusCode::BAD_REQUEST)
+        .await
+        .expect_err("expected bad request");
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(testctx.events_as_string().is_empty());
+
+    testctx.teardown().await;
+}
@@ -0,0 +1,58 @@
+use camino::Utf8PathBuf;
+use dropshot::test_util::object_get;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use chimney_server::{Health, ReadinessReport};
+
+use crate::test_util::{DEFAULT_CONFIG_PATH, EventLogMode, TestContext};
+
+#[tokio::test]
+async fn healthz() {
+    let testctx = TestContext::new("healthz", DEFAULT_CONFIG_PATH, EventLogMode::None);
+    let health = object_get::<Health>(&testctx.inner.client_testctx, "/healthz").await;
+    assert_eq!("ok", health.status);
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn readyz_ready() {
+    let testctx = TestContext::new("readyz_ready", DEFAULT_CONFIG_PATH, EventLogMode::None);
+    let report = object_get::<ReadinessReport>(&testctx.inner.client_testctx, "/readyz").await;
+    assert!(report.ready);
+    assert!(report.checks["config_store"].ok);
+    assert!(!report.checks.contains_key("event_log"));
+    assert_eq!(None, report.last_reload_error);
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn readyz_unwritable_event_log() {
+    let testctx = TestContext::new_with(
+        "readyz_unwritable_event_log",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| {
+            // A directory cannot be opened as the event log.
+            context.readiness.event_log_path =
+                Some(Utf8PathBuf::from_path_buf(std::env::temp_dir()).unwrap());
+        },
+    );
+    // The dropshot test client expects error responses to have its own error body.
+    let mut stream = TcpStream::connect(testctx.inner.server.local_addr())
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET /readyz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 503"));
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    let report = serde_json::from_str::<ReadinessReport>(body).unwrap();
+    assert!(!report.ready);
+    assert!(report.checks["config_store"].ok);
+    assert!(!report.checks["event_log"].ok);
+    testctx.teardown().await;
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use dropshot::test_util::read_json;
+use dropshot::{Body, HttpErrorResponseBody};
+use http::Method;
+use http::header::AUTHORIZATION;
+
+use chimney_server::{Machine, MachineInventory, Machines};
+
+use crate::admin::{AdminTestContext, TOKEN};
+use crate::test_util::{
+    ContentEncoding, DEFAULT_CONFIG_PATH, EventLogMode, MachineId, SyncRequests, TestContext,
+    build_request,
+};
+
+const PREFIX_URI: &str = "/admin/machines";
+
+fn build_uri(machine_id: &str) -> String {
+    format!("{PREFIX_URI}/{machine_id}")
+}
+
+/// Sends a GET request to the admin API and returns the response if it has the expected status.
+async fn admin_get(
+    testctx: &AdminTestContext,
+    uri: &str,
+    token: &str,
+    status: http::StatusCode,
+) -> Result<hyper::Response<Body>, HttpErrorResponseBody> {
+    let client_testctx = &testctx.inner.client_testctx;
+    let request = hyper::Request::builder()
+        .method(Method::GET)
+        .uri(client_testctx.url(uri))
+        .header(AUTHORIZATION, format!("Bearer {token}"))
+        .body(Body::empty())
+        .expect("invalid request");
+    client_testctx
+        .make_request_with_request(request, status)
+        .await
+}
+
+#[tokio::test]
+async fn machines_records_preflight_and_postflight() {
+    let machine_id = MachineId::One.to_string();
+    let inventory = Arc::new(MachineInventory::in_memory());
+    let testctx = TestContext::new_with(
+        "machines_records_preflight_and_postflight",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| context.inventory = Some(inventory.clone()),
+    );
+    let admin_testctx = AdminTestContext::new_with(
+        "machines_records_preflight_and_postflight_admin",
+        DEFAULT_CONFIG_PATH,
+        |context| context.inventory = Some(inventory.clone()),
+    );
+    let client_testctx = &testctx.inner.client_testctx;
+    let request_body = r#"{
+        "serial_num": "serial_num",
+        "hostname": "hostname",
+        "os_version": "os_version",
+        "os_build": "os_build",
+        "santa_version": "santa_version",
+        "primary_user": "primary_user",
+        "client_mode": "MONITOR"
+    }"#;
+    let uri = client_testctx.url(&format!("/preflight/{machine_id}"));
+    let request = build_request(request_body, &ContentEncoding::Deflate, uri);
+    client_testctx
+        .make_sync_request(request, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    let request_body = r#"{
+        "rules_received": 8,
+        "rules_processed": 8
+    }"#;
+    let uri = client_testctx.url(&format!("/postflight/{machine_id}"));
+    let request = build_request(request_body, &ContentEncoding::Deflate, uri);
+    client_testctx
+        .make_sync_request(request, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+
+    let mut response = admin_get(&admin_testctx, PREFIX_URI, TOKEN, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    let machines = read_json::<Machines>(&mut response).await;
+    assert_eq!(1, machines.machines.len());
+    let mut response = admin_get(
+        &admin_testctx,
+        &build_uri(&machine_id),
+        TOKEN,
+        http::StatusCode::OK,
+    )
+    .await
+    .expect("expected success");
+    let machine = read_json::<Machine>(&mut response).await;
+    assert_eq!(machine_id, machine.machine_id);
+    assert_eq!(Some(machine_id.clone()), machine.client_config);
+    assert_eq!("hostname", machine.last_preflight.unwrap().hostname);
+    assert_eq!(Some(8), machine.last_postflight.unwrap().rules_processed);
+
+    admin_testctx.teardown().await;
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn machines_require_an_admin_token() {
+    let testctx = AdminTestContext::new_with(
+        "machines_require_an_admin_token",
+        DEFAULT_CONFIG_PATH,
+        |context| context.inventory = Some(Arc::new(MachineInventory::in_memory())),
+    );
+
+    let err = admin_get(
+        &testctx,
+        PREFIX_URI,
+        "wrong-token",
+        http::StatusCode::UNAUTHORIZED,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.message, "invalid or missing bearer token");
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn machines_are_not_served_on_the_sync_port() {
+    let testctx = TestContext::new_with(
+        "machines_are_not_served_on_the_sync_port",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| context.inventory = Some(Arc::new(MachineInventory::in_memory())),
+    );
+    for uri in ["/machines", "/admin/machines"] {
+        let request = hyper::Request::builder()
+            .method(Method::GET)
+            .uri(testctx.inner.client_testctx.url(uri))
+            .body(Body::empty())
+            .expect("invalid request");
+        testctx
+            .inner
+            .client_testctx
+            .make_sync_request(request, http::StatusCode::NOT_FOUND)
+            .await
+            .expect_err("expected not found");
+    }
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn machines_unknown_machine_id() {
+    let testctx = AdminTestContext::new_with(
+        "machines_unknown_machine_id",
+        DEFAULT_CONFIG_PATH,
+        |context| context.inventory = Some(Arc::new(MachineInventory::in_memory())),
+    );
+
+    let err = admin_get(
+        &testctx,
+        &build_uri("unknown"),
+        TOKEN,
+        http::StatusCode::NOT_FOUND,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.message, "machine \"unknown\" not found");
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn machines_inventory_disabled() {
+    let testctx = AdminTestContext::new("machines_inventory_disabled");
+
+    let err = admin_get(&testctx, PREFIX_URI, TOKEN, http::StatusCode::NOT_FOUND)
+        .await
+        .unwrap_err();
+    assert_eq!(err.message, "machine inventory is not enabled");
+
+    testctx.teardown().await;
+}
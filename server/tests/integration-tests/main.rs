@@ -1,7 +1,15 @@
+mod admin;
 mod compressed_typed_body;
 mod eventupload;
+mod health;
 mod load_test;
+mod machines;
+mod metrics;
+mod openapi;
 mod postflight;
 mod preflight;
 mod ruledownload;
+mod simulate;
+mod sync_auth;
 mod test_util;
+mod tls;
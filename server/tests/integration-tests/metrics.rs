@@ -0,0 +1,96 @@
+use dropshot::test_util::read_string;
+use http::Method;
+
+use crate::test_util::{
+    ContentEncoding, DEFAULT_CONFIG_PATH, EventLogMode, MachineId, SyncRequests, TestContext,
+    build_request,
+};
+
+const PREFIX_URI: &str = "/metrics";
+const PROMETHEUS_TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+#[tokio::test]
+async fn metrics_disabled() {
+    let testctx = TestContext::new("metrics_disabled", DEFAULT_CONFIG_PATH, EventLogMode::None);
+    testctx
+        .inner
+        .client_testctx
+        .make_request_no_body(Method::GET, PREFIX_URI, http::StatusCode::NOT_FOUND)
+        .await
+        .expect_err("expected not found");
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn metrics_reports_requests_and_decompression_failures() {
+    let testctx = TestContext::new_with(
+        "metrics_reports_requests_and_decompression_failures",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| context.metrics_enabled = true,
+    );
+    let client_testctx = &testctx.inner.client_testctx;
+    let uri = client_testctx.url(&format!("/ruledownload/{}", MachineId::One));
+    let request = build_request("{}", &ContentEncoding::Deflate, uri);
+    client_testctx
+        .make_sync_request(request, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    let uri = client_testctx.url(&format!("/ruledownload/{}", MachineId::One));
+    let mut request = build_request("{}", &ContentEncoding::Gzip, uri);
+    *request.body_mut() = "not gzip".into();
+    client_testctx
+        .make_sync_request(request, http::StatusCode::BAD_REQUEST)
+        .await
+        .expect_err("expected bad request");
+
+    let mut response = client_testctx
+        .make_request_no_body(Method::GET, PREFIX_URI, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    assert_eq!(
+        PROMETHEUS_TEXT_FORMAT,
+        response.headers()[http::header::CONTENT_TYPE]
+    );
+    let metrics = read_string(&mut response).await;
+    assert!(metrics.contains(r#"chimney_requests_total{endpoint="ruledownload",status="200"}"#));
+    assert!(metrics.contains(r#"chimney_requests_total{endpoint="ruledownload",status="400"}"#));
+    assert!(metrics.contains(r#"chimney_decompression_failures_total{content_encoding="gzip"}"#));
+    assert!(metrics.contains("chimney_rules_served_count"));
+    assert!(metrics.contains("chimney_client_configs"));
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn metrics_counts_unsupported_content_encodings() {
+    let testctx = TestContext::new_with(
+        "metrics_counts_unsupported_content_encodings",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| context.metrics_enabled = true,
+    );
+    let client_testctx = &testctx.inner.client_testctx;
+    for endpoint in ["preflight", "eventupload"] {
+        let uri = client_testctx.url(&format!("/{endpoint}/{}", MachineId::One));
+        let mut request = build_request("{}", &ContentEncoding::Deflate, uri);
+        request.headers_mut().insert(
+            http::header::CONTENT_ENCODING,
+            http::HeaderValue::from_static("compress"),
+        );
+        client_testctx
+            .make_sync_request(request, http::StatusCode::UNSUPPORTED_MEDIA_TYPE)
+            .await
+            .expect_err("expected unsupported media type");
+    }
+
+    let mut response = client_testctx
+        .make_request_no_body(Method::GET, PREFIX_URI, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    let metrics = read_string(&mut response).await;
+    assert!(metrics.contains(r#"chimney_requests_total{endpoint="preflight",status="415"}"#));
+    assert!(metrics.contains(r#"chimney_requests_total{endpoint="eventupload",status="415"}"#));
+
+    testctx.teardown().await;
+}
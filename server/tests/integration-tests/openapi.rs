@@ -0,0 +1,29 @@
+use chimney_server::{Api, write_openapi};
+
+/// Compares a generated OpenAPI document with the one checked in under `server/openapi`. Set
+/// `CHIMNEY_UPDATE_OPENAPI=1` to write the generated document instead.
+fn check_openapi(api: Api, file_name: &str) {
+    let path = format!("{}/openapi/{file_name}", env!("CARGO_MANIFEST_DIR"));
+    let mut generated = vec![];
+    write_openapi(api, &mut generated).unwrap();
+    let generated = String::from_utf8(generated).unwrap();
+    if std::env::var_os("CHIMNEY_UPDATE_OPENAPI").is_some() {
+        std::fs::write(&path, &generated).unwrap();
+        return;
+    }
+    let checked_in = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        checked_in == generated,
+        "{path} is out of date, rerun the tests with CHIMNEY_UPDATE_OPENAPI=1 to update it"
+    );
+}
+
+#[test]
+fn sync_openapi_is_up_to_date() {
+    check_openapi(Api::Sync, "sync.json");
+}
+
+#[test]
+fn admin_openapi_is_up_to_date() {
+    check_openapi(Api::Admin, "admin.json");
+}
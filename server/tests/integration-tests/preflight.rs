@@ -1,10 +1,28 @@
+use std::io::Read;
+
 use dropshot::test_util::read_json;
+use flate2::read::MultiGzDecoder;
+use http::HeaderValue;
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, VARY};
+use http_body_util::BodyExt;
+
+use chimney_server::body::DecodeLimit;
 
 use crate::test_util::{
-    ContentEncoding, DEFAULT_CONFIG_PATH, EventLogMode, MachineId, TestContext, build_request,
+    ContentEncoding, DEFAULT_CONFIG_PATH, EventLogMode, MachineId, SELECTORS_CONFIG_PATH,
+    SyncRequests, TestContext, build_request,
 };
 
 const PREFIX_URI: &str = "/preflight";
+const PREFLIGHT_REQUEST_BODY: &str = r#"{
+    "serial_num": "serial_num",
+    "hostname": "hostname",
+    "os_version": "os_version",
+    "os_build": "os_build",
+    "santa_version": "santa_version",
+    "primary_user": "primary_user",
+    "client_mode": "MONITOR"
+}"#;
 
 fn build_uri(machine_id: &str) -> String {
     format!("{PREFIX_URI}/{machine_id}")
@@ -33,7 +51,7 @@ async fn preflight_deflate_specified_machine_id() {
     let mut response = testctx
         .inner
         .client_testctx
-        .make_request_with_request(request, http::StatusCode::OK)
+        .make_sync_request(request, http::StatusCode::OK)
         .await
         .expect("expected success");
     let preflight = read_json::<santa_types::Preflight>(&mut response).await;
@@ -67,7 +85,7 @@ async fn preflight_gzip_specified_machine_id() {
     let mut response = testctx
         .inner
         .client_testctx
-        .make_request_with_request(request, http::StatusCode::OK)
+        .make_sync_request(request, http::StatusCode::OK)
         .await
         .expect("expected success");
     let preflight = read_json::<santa_types::Preflight>(&mut response).await;
@@ -77,3 +95,164 @@ async fn preflight_gzip_specified_machine_id() {
 
     testctx.teardown().await;
 }
+
+#[tokio::test]
+async fn preflight_selected_config_is_used_for_the_sync_session() {
+    let machine_id = "unknown-machine-id";
+    let testctx = TestContext::new(
+        "preflight_selected_config_is_used_for_the_sync_session",
+        SELECTORS_CONFIG_PATH,
+        EventLogMode::None,
+    );
+    let client_testctx = &testctx.inner.client_testctx;
+    let selected_config = testctx.config_for("by-serial");
+    let global_config = testctx.config_for("global");
+    let request_body = r#"{
+        "serial_num": "C02SERIAL",
+        "hostname": "hostname",
+        "os_version": "os_version",
+        "os_build": "os_build",
+        "santa_version": "santa_version",
+        "client_mode": "MONITOR"
+    }"#;
+    let uri = client_testctx.url(&build_uri(machine_id));
+    let request = build_request(request_body, &ContentEncoding::Deflate, uri);
+    let mut response = client_testctx
+        .make_sync_request(request, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    let preflight = read_json::<santa_types::Preflight>(&mut response).await;
+    assert_eq!(selected_config.preflight, preflight);
+
+    let uri = client_testctx.url(&format!("/ruledownload/{machine_id}"));
+    let request = build_request(r#"{}"#, &ContentEncoding::Deflate, uri);
+    let mut response = client_testctx
+        .make_sync_request(request, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    let rules = read_json::<santa_types::Rules>(&mut response).await;
+    assert_eq!(selected_config.rules, rules.rules);
+
+    let uri = client_testctx.url(&format!("/postflight/{machine_id}"));
+    let request = build_request(r#"{}"#, &ContentEncoding::Deflate, uri);
+    client_testctx
+        .make_sync_request(request, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+
+    let uri = client_testctx.url(&format!("/ruledownload/{machine_id}"));
+    let request = build_request(r#"{}"#, &ContentEncoding::Deflate, uri);
+    let mut response = client_testctx
+        .make_sync_request(request, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    let rules = read_json::<santa_types::Rules>(&mut response).await;
+    assert_eq!(global_config.rules, rules.rules);
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn preflight_decoded_body_too_large() {
+    let machine_id = MachineId::One.to_string();
+    let testctx = TestContext::new_with(
+        "preflight_decoded_body_too_large",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| {
+            context.decode_limits.endpoints.insert(
+                "preflight".to_string(),
+                DecodeLimit {
+                    max_compression_ratio: None,
+                    max_decoded_bytes: Some(64),
+                },
+            );
+        },
+    );
+    let request_body = r#"{
+        "serial_num": "serial_num",
+        "hostname": "hostname",
+        "os_version": "os_version",
+        "os_build": "os_build",
+        "santa_version": "santa_version",
+        "primary_user": "primary_user",
+        "client_mode": "MONITOR"
+    }"#;
+    let uri = testctx.inner.client_testctx.url(&build_uri(&machine_id));
+    let request = build_request(request_body, &ContentEncoding::Gzip, uri);
+
+    let err = testctx
+        .inner
+        .client_testctx
+        .make_sync_request(request, http::StatusCode::PAYLOAD_TOO_LARGE)
+        .await
+        .expect_err("expected payload too large");
+    assert_eq!("decoded request body exceeds 64 bytes", err.message);
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn preflight_compressed_response() {
+    let machine_id = MachineId::One.to_string();
+    let testctx = TestContext::new_with(
+        "preflight_compressed_response",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| context.response_compression.min_bytes = 0,
+    );
+    let uri = testctx.inner.client_testctx.url(&build_uri(&machine_id));
+    let mut request = build_request(PREFLIGHT_REQUEST_BODY, &ContentEncoding::Gzip, uri);
+    request
+        .headers_mut()
+        .insert(ACCEPT_ENCODING, HeaderValue::from_static("deflate, gzip"));
+
+    // The dropshot test client rejects Content-Encoding in responses, so bypass its checks.
+    let response = testctx
+        .inner
+        .client_testctx
+        .client
+        .request(request)
+        .await
+        .expect("expected success");
+    assert_eq!(http::StatusCode::OK, response.status());
+    assert_eq!("gzip", response.headers()[CONTENT_ENCODING]);
+    assert_eq!("accept-encoding", response.headers()[VARY]);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let mut decoded = String::new();
+    MultiGzDecoder::new(&body[..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    let preflight = serde_json::from_str::<santa_types::Preflight>(&decoded).unwrap();
+    assert_eq!(testctx.config_for(&machine_id).preflight, preflight);
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn preflight_response_below_compression_threshold() {
+    let machine_id = MachineId::One.to_string();
+    let testctx = TestContext::new(
+        "preflight_response_below_compression_threshold",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+    );
+    let uri = testctx.inner.client_testctx.url(&build_uri(&machine_id));
+    let mut request = build_request(PREFLIGHT_REQUEST_BODY, &ContentEncoding::Gzip, uri);
+    request
+        .headers_mut()
+        .insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+
+    let mut response = testctx
+        .inner
+        .client_testctx
+        .make_sync_request(request, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    assert_eq!("accept-encoding", response.headers()[VARY]);
+    let preflight = read_json::<santa_types::Preflight>(&mut response).await;
+    assert_eq!(testctx.config_for(&machine_id).preflight, preflight);
+
+    testctx.teardown().await;
+}
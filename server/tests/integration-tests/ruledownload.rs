@@ -1,7 +1,8 @@
 use dropshot::test_util::read_json;
 
 use crate::test_util::{
-    ContentEncoding, DEFAULT_CONFIG_PATH, EventLogMode, MachineId, TestContext, build_request,
+    ContentEncoding, DEFAULT_CONFIG_PATH, EventLogMode, MachineId, SyncRequests, TestContext,
+    build_request,
 };
 
 const PREFIX_URI: &str = "/ruledownload";
@@ -25,7 +26,7 @@ async fn ruledownload_deflate_specified_machine_id() {
     let mut response = testctx
         .inner
         .client_testctx
-        .make_request_with_request(request, http::StatusCode::OK)
+        .make_sync_request(request, http::StatusCode::OK)
         .await
         .expect("expected success");
     let rules = read_json::<santa_types::Rules>(&mut response).await;
@@ -51,7 +52,7 @@ async fn ruledownload_gzip_specified_machine_id() {
     let mut response = testctx
         .inner
         .client_testctx
-        .make_request_with_request(request, http::StatusCode::OK)
+        .make_sync_request(request, http::StatusCode::OK)
         .await
         .expect("expected success");
     let rules = read_json::<santa_types::Rules>(&mut response).await;
@@ -61,3 +62,114 @@ async fn ruledownload_gzip_specified_machine_id() {
 
     testctx.teardown().await;
 }
+
+#[tokio::test]
+async fn ruledownload_pages_rules_with_cursor() {
+    let machine_id = MachineId::One.to_string();
+    let testctx = TestContext::new_with(
+        "ruledownload_pages_rules_with_cursor",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| context.rule_download_page_size = 3,
+    );
+    let client_config = testctx.config_for(&machine_id);
+    let mut downloaded = vec![];
+    let mut cursors = vec![];
+    let mut request_body = r#"{}"#.to_string();
+
+    loop {
+        let uri = testctx.inner.client_testctx.url(&build_uri(&machine_id));
+        let request = build_request(&request_body, &ContentEncoding::Deflate, uri);
+        let mut response = testctx
+            .inner
+            .client_testctx
+            .make_sync_request(request, http::StatusCode::OK)
+            .await
+            .expect("expected success");
+        let rules = read_json::<santa_types::Rules>(&mut response).await;
+        assert!(rules.rules.len() <= 3);
+        downloaded.extend(rules.rules);
+        match rules.cursor {
+            Some(cursor) => {
+                request_body = serde_json::to_string(&santa_types::RuleDownloadOptions {
+                    cursor: Some(cursor.clone()),
+                })
+                .unwrap();
+                cursors.push(cursor);
+            }
+            None => break,
+        }
+    }
+
+    assert_eq!(2, cursors.len());
+    let (version, offset) = cursors[0].split_once('-').unwrap();
+    assert_eq!("3", offset);
+    assert_eq!(format!("{version}-6"), cursors[1]);
+    assert_eq!(client_config.rules, downloaded);
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn ruledownload_rejects_cursor_for_changed_rules() {
+    let testctx = TestContext::new_with(
+        "ruledownload_rejects_cursor_for_changed_rules",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| context.rule_download_page_size = 3,
+    );
+    let client_testctx = &testctx.inner.client_testctx;
+    let uri = client_testctx.url(&build_uri(&MachineId::One.to_string()));
+    let request = build_request("{}", &ContentEncoding::Deflate, uri);
+    let mut response = client_testctx
+        .make_sync_request(request, http::StatusCode::OK)
+        .await
+        .expect("expected success");
+    let cursor = read_json::<santa_types::Rules>(&mut response)
+        .await
+        .cursor
+        .expect("expected a cursor");
+
+    // The global config's rules stand in for rules that changed between pages.
+    let request_body = serde_json::to_string(&santa_types::RuleDownloadOptions {
+        cursor: Some(cursor.clone()),
+    })
+    .unwrap();
+    let uri = client_testctx.url(&build_uri("unknown-machine"));
+    let request = build_request(&request_body, &ContentEncoding::Deflate, uri);
+    let err = client_testctx
+        .make_sync_request(request, http::StatusCode::BAD_REQUEST)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.message,
+        format!(
+            "cursor \"{cursor}\" is for rules that have changed since, restart the rule download"
+        )
+    );
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn ruledownload_invalid_cursor() {
+    let machine_id = MachineId::One.to_string();
+    let testctx = TestContext::new(
+        "ruledownload_invalid_cursor",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+    );
+    let request_body = r#"{"cursor": "not-a-cursor"}"#;
+    let uri = testctx.inner.client_testctx.url(&build_uri(&machine_id));
+    let request = build_request(request_body, &ContentEncoding::Deflate, uri);
+
+    let response = testctx
+        .inner
+        .client_testctx
+        .make_sync_request(request, http::StatusCode::BAD_REQUEST)
+        .await;
+    let err = response.unwrap_err();
+    assert_eq!(err.message, "invalid cursor \"not-a-cursor\"");
+
+    testctx.teardown().await;
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use http::HeaderMap;
+
+use chimney_server::{
+    SimulateOptions, SyncAuth, SyncAuthConfig, parse_header, read_events, simulate,
+    simulated_preflight,
+};
+
+use crate::test_util::{DEFAULT_CONFIG_PATH, EventLogMode, MachineId, TestContext};
+
+const EVENTS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/simulate/events.json");
+
+fn simulate_options(
+    testctx: &TestContext<chimney_server::Context>,
+    headers: HeaderMap,
+) -> SimulateOptions {
+    SimulateOptions {
+        url: format!("http://{}", testctx.inner.server.local_addr())
+            .parse()
+            .unwrap(),
+        machine_id: MachineId::One.to_string(),
+        preflight: simulated_preflight(),
+        events: read_events(EVENTS_PATH).unwrap(),
+        headers,
+        tls: None,
+    }
+}
+
+#[tokio::test]
+async fn simulate_runs_a_full_sync() {
+    let testctx = TestContext::new_with(
+        "simulate_runs_a_full_sync",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::Persist,
+        |context| context.rule_download_page_size = 3,
+    );
+    let report = simulate(&simulate_options(&testctx, HeaderMap::new()))
+        .await
+        .unwrap();
+
+    let config = testctx.config_for(&MachineId::One.to_string());
+    assert_eq!(config.preflight, report.preflight);
+    assert_eq!(3, report.events_uploaded);
+    assert_eq!(1, report.eventupload_requests);
+    assert_eq!(config.rules, report.rules);
+    assert_eq!(3, report.ruledownload_requests);
+    assert_eq!(3, testctx.events_as_string().len());
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn simulate_sends_extra_headers() {
+    let config = SyncAuthConfig {
+        header: "x-chimney-token".to_string(),
+        token_file: None,
+        tokens: vec!["token".to_string()],
+    };
+    let testctx = TestContext::new_with(
+        "simulate_sends_extra_headers",
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| context.sync_auth = Some(Arc::new(SyncAuth::from_config(&config).unwrap())),
+    );
+    let err = simulate(&simulate_options(&testctx, HeaderMap::new()))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("preflight returned 401"));
+
+    let headers = HeaderMap::from_iter([parse_header("X-Chimney-Token: token").unwrap()]);
+    assert!(simulate(&simulate_options(&testctx, headers)).await.is_ok());
+    testctx.teardown().await;
+}
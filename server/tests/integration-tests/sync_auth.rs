@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use http::HeaderValue;
+
+use chimney_server::{SyncAuth, SyncAuthConfig};
+
+use crate::test_util::{
+    ContentEncoding, DEFAULT_CONFIG_PATH, EventLogMode, MachineId, SyncRequests, TestContext,
+    build_request,
+};
+
+const PREFIX_URI: &str = "/preflight";
+const HEADER: &str = "x-chimney-token";
+
+fn build_uri(machine_id: &str) -> String {
+    format!("{PREFIX_URI}/{machine_id}")
+}
+
+fn new_testctx(test_name: &str) -> TestContext<chimney_server::Context> {
+    let config = SyncAuthConfig {
+        header: HEADER.to_string(),
+        token_file: None,
+        tokens: vec!["old-token".to_string(), "new-token".to_string()],
+    };
+    TestContext::new_with(
+        test_name,
+        DEFAULT_CONFIG_PATH,
+        EventLogMode::None,
+        |context| context.sync_auth = Some(Arc::new(SyncAuth::from_config(&config).unwrap())),
+    )
+}
+
+async fn preflight(
+    testctx: &TestContext<chimney_server::Context>,
+    token: Option<&'static str>,
+    status: http::StatusCode,
+) {
+    let request_body = r#"{
+        "serial_num": "serial_num",
+        "hostname": "hostname",
+        "os_version": "os_version",
+        "os_build": "os_build",
+        "santa_version": "santa_version",
+        "primary_user": "primary_user",
+        "client_mode": "MONITOR"
+    }"#;
+    let uri = testctx
+        .inner
+        .client_testctx
+        .url(&build_uri(&MachineId::One.to_string()));
+    let mut request = build_request(request_body, &ContentEncoding::Deflate, uri);
+    if let Some(token) = token {
+        request
+            .headers_mut()
+            .insert(HEADER, HeaderValue::from_static(token));
+    }
+    let _ = testctx
+        .inner
+        .client_testctx
+        .make_sync_request(request, status)
+        .await;
+}
+
+#[tokio::test]
+async fn sync_auth_accepts_every_configured_token() {
+    let testctx = new_testctx("sync_auth_accepts_every_configured_token");
+    preflight(&testctx, Some("old-token"), http::StatusCode::OK).await;
+    preflight(&testctx, Some("new-token"), http::StatusCode::OK).await;
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn sync_auth_rejects_invalid_token() {
+    let testctx = new_testctx("sync_auth_rejects_invalid_token");
+    preflight(&testctx, None, http::StatusCode::UNAUTHORIZED).await;
+    preflight(
+        &testctx,
+        Some("wrong-token"),
+        http::StatusCode::UNAUTHORIZED,
+    )
+    .await;
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn sync_auth_rejects_before_reading_the_body() {
+    let testctx = new_testctx("sync_auth_rejects_before_reading_the_body");
+    let uri = testctx
+        .inner
+        .client_testctx
+        .url(&build_uri(&MachineId::One.to_string()));
+    // The body is not deflate data, so decoding it would fail before authorization.
+    let mut request = build_request("", &ContentEncoding::Deflate, uri);
+    *request.body_mut() = dropshot::Body::from("not deflate data");
+    let err = testctx
+        .inner
+        .client_testctx
+        .make_sync_request(request, http::StatusCode::UNAUTHORIZED)
+        .await
+        .expect_err("expected unauthorized");
+    assert_eq!("invalid or missing sync token", err.message);
+    testctx.teardown().await;
+}
@@ -1,24 +1,30 @@
 use std::fmt::{Display, Formatter, Result};
 use std::io::prelude::*;
+use std::sync::Arc;
 
 use camino::Utf8PathBuf;
 use dropshot::ServerContext;
 use dropshot::test_util::TestContext as DropshotTestContext;
-use dropshot::test_util::{LogContext, log_file_for_test};
+use dropshot::test_util::{ClientTestContext, LogContext, log_file_for_test, read_json};
 use dropshot::{
-    Body, ConfigDropshot, ConfigLogging, ConfigLoggingIfExists, ConfigLoggingLevel, HandlerTaskMode,
+    Body, ConfigDropshot, ConfigLogging, ConfigLoggingIfExists, ConfigLoggingLevel,
+    HandlerTaskMode, HttpErrorResponseBody,
 };
 use flate2::Compression;
 use flate2::write::{GzEncoder, ZlibEncoder};
-use http::Method;
 use http::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use http::{Method, Response, StatusCode};
 use serde::Serialize;
 use slog::{Logger, o};
 
-use chimney_server::{ClientConfig, ConfigStore, Context, EventLogging, api};
+use chimney_server::{ClientConfig, ConfigStore, Context, EventLogging, SharedConfigStore, api};
 
 pub const DEFAULT_CONFIG_PATH: &str =
     concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tomls/client-tomls/good");
+pub const SELECTORS_CONFIG_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/tomls/client-tomls/selectors"
+);
 
 pub enum MachineId {
     One,
@@ -34,6 +40,36 @@ impl Display for MachineId {
     }
 }
 
+/// Sync responses carry headers the dropshot test client rejects, e.g. `Vary`, so sync requests
+/// only check the response status.
+pub trait SyncRequests {
+    async fn make_sync_request(
+        &self,
+        request: hyper::Request<Body>,
+        expected_status: StatusCode,
+    ) -> std::result::Result<Response<Body>, HttpErrorResponseBody>;
+}
+
+impl SyncRequests for ClientTestContext {
+    async fn make_sync_request(
+        &self,
+        request: hyper::Request<Body>,
+        expected_status: StatusCode,
+    ) -> std::result::Result<Response<Body>, HttpErrorResponseBody> {
+        let response = self
+            .client
+            .request(request)
+            .await
+            .expect("failed to make request to server");
+        assert_eq!(expected_status, response.status());
+        let mut response = response.map(Body::wrap);
+        if expected_status.is_success() {
+            return Ok(response);
+        }
+        Err(read_json(&mut response).await)
+    }
+}
+
 pub enum EventLogMode {
     None,
     Persist,
@@ -46,6 +82,16 @@ pub struct TestContext<Context: ServerContext> {
 
 impl TestContext<Context> {
     pub fn new(test_name: &str, config_path: &str, event_logging: EventLogMode) -> Self {
+        Self::new_with(test_name, config_path, event_logging, |_| {})
+    }
+
+    /// Allows a test to adjust the server context before the server starts.
+    pub fn new_with<F: FnOnce(&mut Context)>(
+        test_name: &str,
+        config_path: &str,
+        event_logging: EventLogMode,
+        configure: F,
+    ) -> Self {
         let (event_logctx, event_log) = match event_logging {
             EventLogMode::None => (None, None),
             EventLogMode::Persist => {
@@ -54,8 +100,21 @@ impl TestContext<Context> {
                 (Some(ctx), Some(log))
             }
         };
-        let store = ConfigStore::from_path(config_path).unwrap();
-        let context = Context { event_log, store };
+        let store = SharedConfigStore::new(ConfigStore::from_path(config_path).unwrap());
+        let mut context = Context {
+            client_identities: None,
+            decode_limits: Default::default(),
+            event_log,
+            inventory: None,
+            metrics_enabled: false,
+            readiness: Default::default(),
+            response_compression: Default::default(),
+            store,
+            rule_download_page_size: chimney_server::DEFAULT_RULE_DOWNLOAD_PAGE_SIZE,
+            sessions: Default::default(),
+            sync_auth: None,
+        };
+        configure(&mut context);
         let default_handler_task_mode = HandlerTaskMode::Detached;
         let default_request_body_max_bytes = chimney_server::DEFAULT_REQUEST_BODY_MAX_BYTES;
         let config_dropshot: ConfigDropshot = ConfigDropshot {
@@ -72,7 +131,7 @@ impl TestContext<Context> {
         }
     }
 
-    pub fn config_for(&self, machine_id: &str) -> ClientConfig {
+    pub fn config_for(&self, machine_id: &str) -> Arc<ClientConfig> {
         self.inner.server.app_private().store.config_for(machine_id)
     }
 
@@ -180,11 +239,29 @@ pub fn gzip_encode<T: Serialize>(request: &T) -> EncodedBody {
 }
 
 pub fn gzip_encode_str(serialized: &str) -> EncodedBody {
+    gzip_encode_bytes(serialized.as_bytes())
+}
+
+pub fn gzip_encode_bytes(bytes: &[u8]) -> EncodedBody {
     let mut gz = GzEncoder::new(vec![], Compression::default());
-    gz.write_all(serialized.as_bytes()).unwrap();
+    gz.write_all(bytes).unwrap();
     gz.finish().unwrap()
 }
 
+#[cfg(feature = "br")]
+pub fn brotli_encode<T: Serialize>(request: &T) -> EncodedBody {
+    let serialized = serde_json::to_string(request).unwrap();
+    let mut br = brotli::CompressorWriter::new(vec![], 4096, 5, 22);
+    br.write_all(serialized.as_bytes()).unwrap();
+    br.into_inner()
+}
+
+#[cfg(feature = "zstd")]
+pub fn zstd_encode<T: Serialize>(request: &T) -> EncodedBody {
+    let serialized = serde_json::to_string(request).unwrap();
+    zstd::encode_all(serialized.as_bytes(), 0).unwrap()
+}
+
 pub fn create_log_context(test_name: &str) -> LogContext {
     let log_config = ConfigLogging::File {
         level: ConfigLoggingLevel::Debug,
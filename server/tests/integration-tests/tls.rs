@@ -0,0 +1,191 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use camino::Utf8PathBuf;
+use dropshot::test_util::LogContext;
+use dropshot::{ConfigDropshot, HttpServer, ServerBuilder};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use chimney_server::{
+    ClientIdentities, ConfigStore, Context, SharedConfigStore, TlsConfig, api, start_tls_listener,
+};
+
+use crate::test_util::{DEFAULT_CONFIG_PATH, MachineId, create_log_context};
+
+const TLS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tls");
+
+const PREFLIGHT_BODY: &str = r#"{
+    "serial_num": "serial_num",
+    "hostname": "hostname",
+    "os_version": "os_version",
+    "os_build": "os_build",
+    "santa_version": "santa_version",
+    "primary_user": "primary_user",
+    "client_mode": "MONITOR"
+}"#;
+
+fn tls_file(name: &str) -> Utf8PathBuf {
+    Utf8PathBuf::from(TLS_PATH).join(name)
+}
+
+struct TlsTestContext {
+    server: HttpServer<Context>,
+    /// The address of the TLS listener in front of `server`.
+    local_addr: SocketAddr,
+    logctx: LogContext,
+}
+
+impl TlsTestContext {
+    /// Starts the sync server behind a TLS listener that requires client certificates, the same
+    /// way `start_server` does with `tls_config.client_ca_file` set.
+    async fn new(test_name: &str) -> Self {
+        let tls_config = TlsConfig {
+            cert_file: tls_file("server.pem"),
+            client_ca_file: Some(tls_file("ca.pem")),
+            key_file: tls_file("server.key"),
+        };
+        let identities = Arc::new(ClientIdentities::default());
+        let context = Context {
+            client_identities: Some(identities.clone()),
+            decode_limits: Default::default(),
+            event_log: None,
+            inventory: None,
+            metrics_enabled: false,
+            readiness: Default::default(),
+            response_compression: Default::default(),
+            store: SharedConfigStore::new(ConfigStore::from_path(DEFAULT_CONFIG_PATH).unwrap()),
+            rule_download_page_size: chimney_server::DEFAULT_RULE_DOWNLOAD_PAGE_SIZE,
+            sessions: Default::default(),
+            sync_auth: None,
+        };
+        let logctx = create_log_context(test_name);
+        let server = ServerBuilder::new(api(), context, logctx.log.clone())
+            .config(ConfigDropshot::default())
+            .start()
+            .unwrap();
+        let local_addr = start_tls_listener(
+            "127.0.0.1:0".parse().unwrap(),
+            tls_config.to_client_auth_config().unwrap().unwrap(),
+            server.local_addr(),
+            identities,
+            logctx.log.clone(),
+        )
+        .await
+        .unwrap();
+        TlsTestContext {
+            server,
+            local_addr,
+            logctx,
+        }
+    }
+
+    async fn teardown(self) {
+        self.server.close().await.unwrap();
+        self.logctx.cleanup_successful();
+    }
+}
+
+fn read_certs(name: &str) -> Vec<rustls::pki_types::CertificateDer<'static>> {
+    let pem = std::fs::read(tls_file(name)).unwrap();
+    rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn preflight_request(machine_id: &str) -> String {
+    format!(
+        "POST /preflight/{machine_id} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{PREFLIGHT_BODY}",
+        PREFLIGHT_BODY.len()
+    )
+}
+
+/// Sends a request over TLS and returns the response, or `None` if the server ended the
+/// connection. `client_cert` is `tests/tls/client.pem`, which is issued for `machine-id-1234`.
+async fn request(addr: SocketAddr, client_cert: bool, request: &str) -> Option<String> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs("ca.pem") {
+        roots.add(cert).unwrap();
+    }
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    let config = if client_cert {
+        let key_pem = std::fs::read(tls_file("client.key")).unwrap();
+        let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+            .unwrap()
+            .unwrap();
+        builder
+            .with_client_auth_cert(read_certs("client.pem"), key)
+            .unwrap()
+    } else {
+        builder.with_no_client_auth()
+    };
+    let connector = TlsConnector::from(Arc::new(config));
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let domain = ServerName::try_from("localhost").unwrap();
+    let mut stream = connector.connect(domain, stream).await.ok()?;
+    stream.write_all(request.as_bytes()).await.ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.ok()?;
+    Some(response)
+}
+
+#[tokio::test]
+async fn tls_accepts_client_certificate_from_client_ca() {
+    let testctx = TlsTestContext::new("tls_accepts_client_certificate_from_client_ca").await;
+    let healthz = "GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let response = request(testctx.local_addr, true, healthz)
+        .await
+        .expect("expected a response");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn tls_rejects_missing_client_certificate() {
+    let testctx = TlsTestContext::new("tls_rejects_missing_client_certificate").await;
+    let healthz = "GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    assert!(request(testctx.local_addr, false, healthz).await.is_none());
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn tls_accepts_client_certificate_for_machine_id() {
+    let testctx = TlsTestContext::new("tls_accepts_client_certificate_for_machine_id").await;
+    let preflight = preflight_request(&MachineId::One.to_string());
+    let response = request(testctx.local_addr, true, &preflight)
+        .await
+        .expect("expected a response");
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn tls_rejects_client_certificate_for_another_machine_id() {
+    let testctx =
+        TlsTestContext::new("tls_rejects_client_certificate_for_another_machine_id").await;
+    let preflight = preflight_request(&MachineId::Two.to_string());
+    let response = request(testctx.local_addr, true, &preflight)
+        .await
+        .expect("expected a response");
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+    assert!(response.contains("client certificate was not issued for machine"));
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn tls_rejects_requests_that_bypass_the_tls_listener() {
+    let testctx = TlsTestContext::new("tls_rejects_requests_that_bypass_the_tls_listener").await;
+    let mut stream = TcpStream::connect(testctx.server.local_addr())
+        .await
+        .unwrap();
+    let preflight = preflight_request(&MachineId::One.to_string());
+    stream.write_all(preflight.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+    testctx.teardown().await;
+}
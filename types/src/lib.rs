@@ -10,10 +10,10 @@ mod postflight;
 mod preflight;
 mod rule_download;
 
-pub use event_upload::{Event, EventUploadOptions};
+pub use event_upload::{Decision, Event, EventUploadOptions};
 pub use postflight::PostflightOptions;
-pub use preflight::{Preflight, PreflightOptions};
-pub use rule_download::{Rule, Rules};
+pub use preflight::{ClientMode, OverrideFileAccessAction, Preflight, PreflightOptions, SyncType};
+pub use rule_download::{Policy, Rule, RuleDownloadOptions, RuleType, Rules};
 
 #[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 pub struct Empty {}
@@ -5,6 +5,14 @@ use serde::{Deserialize, Serialize};
 pub struct Rules {
     #[serde(default)]
     pub rules: Vec<Rule>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub cursor: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct RuleDownloadOptions {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub cursor: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
@@ -38,7 +46,7 @@ pub enum Policy {
     SilentBlocklist,
 }
 
-#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
 pub enum RuleType {
     #[serde(rename = "BINARY")]
     Binary,